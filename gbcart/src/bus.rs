@@ -0,0 +1,63 @@
+use crate::pins::CartridgePins;
+use rppal::gpio::{Level, Mode};
+
+pub const BANK_SIZE: usize = 0x4000;
+
+/// Releases the control lines, so the cartridge does not drive the bus
+pub fn idle_bus<P: CartridgePins + ?Sized>(pins: &mut P) {
+    pins.set_cs(Level::High);
+    pins.set_read(Level::High);
+    pins.set_write(Level::High);
+    pins.delay();
+}
+
+fn shift_out<P: CartridgePins + ?Sized>(pins: &mut P, value: u8) {
+    for number in (0..8).rev() {
+        pins.set_clock(Level::Low);
+        pins.delay();
+        if (value >> number) & 1 == 1 {
+            pins.set_serial_data(Level::High);
+        } else {
+            pins.set_serial_data(Level::Low);
+        }
+        pins.delay();
+        pins.set_clock(Level::High);
+        pins.delay();
+    }
+}
+
+fn write_address<P: CartridgePins + ?Sized>(pins: &mut P, address: u16) {
+    pins.set_latch(Level::Low);
+    shift_out(pins, (address >> 8) as u8);
+    shift_out(pins, (address & 0xFF) as u8);
+    pins.set_latch(Level::High);
+}
+
+pub fn read_byte<P: CartridgePins + ?Sized>(pins: &mut P, address: u16) -> u8 {
+    write_address(pins, address);
+    pins.set_cs(Level::Low);
+    pins.set_read(Level::Low);
+    pins.delay();
+    let value = pins.read_data();
+    pins.set_read(Level::High);
+    pins.set_cs(Level::High);
+    value
+}
+
+pub fn write_byte<P: CartridgePins + ?Sized>(pins: &mut P, address: u16, value: u8) {
+    write_address(pins, address);
+    pins.set_data_mode(Mode::Output);
+    pins.write_data(value);
+    pins.set_cs(Level::Low);
+    pins.set_write(Level::Low);
+    pins.delay();
+    pins.set_write(Level::High);
+    pins.set_cs(Level::High);
+    pins.set_data_mode(Mode::Input);
+    pins.delay();
+}
+
+/// Reads the 16 KiB starting at `start_address`
+pub fn read_rom_bank<P: CartridgePins + ?Sized>(pins: &mut P, start_address: u16) -> Vec<u8> {
+    (start_address..=(start_address + 0x3FFF)).map(|address| read_byte(pins, address)).collect()
+}
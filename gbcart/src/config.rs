@@ -0,0 +1,160 @@
+use crate::error::{Error, Result};
+use std::path::Path;
+use std::time::Duration;
+
+const DEFAULT_DELAY: Duration = Duration::from_micros(2);
+
+/// How long the bus waits after changing a line
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BusTiming {
+    Fixed(Duration),
+    /// Measure the fastest reliable delay with `Cartridge::calibrate` when the bus is opened
+    Calibrate,
+}
+
+impl BusTiming {
+    /// Parses a delay in nanoseconds, or `auto` for calibration
+    pub fn parse(value: &str) -> Result<BusTiming> {
+        match value.trim() {
+            "auto" => Ok(BusTiming::Calibrate),
+            nanos => nanos.parse::<u64>()
+                .map(|nanos| BusTiming::Fixed(Duration::from_nanos(nanos)))
+                .map_err(|_| Error::Config(format!("Bus delay {:?} is neither a number of nanoseconds nor auto", value))),
+        }
+    }
+}
+
+/// The wiring of the adapter board and the speed of the bus.
+///
+/// The defaults match the original RaspberryGB board. Other board revisions can describe their
+/// wiring in a config file with one `key = value` pair per line:
+///
+/// ```text
+/// # BCM pin numbers
+/// cs = 18
+/// latch = 24
+/// serial = 23
+/// clock = 25
+/// read = 15
+/// write = 14
+/// data = 8, 7, 19, 12, 16, 20, 21, 26
+/// # nanoseconds, or auto
+/// delay = 2000
+/// ```
+#[derive(Clone, PartialEq, Debug)]
+pub struct BusConfig {
+    pub cs: u8,
+    pub latch: u8,
+    pub serial_data: u8,
+    pub clock: u8,
+    pub read: u8,
+    pub write: u8,
+    /// D0 to D7
+    pub data: [u8; 8],
+    pub timing: BusTiming,
+}
+
+impl Default for BusConfig {
+    fn default() -> BusConfig {
+        BusConfig {
+            cs: 18,
+            latch: 24,
+            serial_data: 23,
+            clock: 25,
+            read: 15,
+            write: 14,
+            data: [8, 7, 19, 12, 16, 20, 21, 26],
+            timing: BusTiming::Fixed(DEFAULT_DELAY),
+        }
+    }
+}
+
+impl BusConfig {
+    /// The fixed delay, or the default one when the delay is still to be calibrated
+    pub fn delay(&self) -> Duration {
+        match self.timing {
+            BusTiming::Fixed(delay) => delay,
+            BusTiming::Calibrate => DEFAULT_DELAY,
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<BusConfig> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("Could not read {}: {}", path.display(), e)))?;
+        BusConfig::parse(&text)
+    }
+
+    /// Parses a config file, keys which are missing keep their default
+    pub fn parse(text: &str) -> Result<BusConfig> {
+        let mut config = BusConfig::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| Error::Config(format!("Line {}: expected key = value", number + 1)))?;
+            let pin = || parse_pin(value, number);
+            match key.trim() {
+                "cs" => config.cs = pin()?,
+                "latch" => config.latch = pin()?,
+                "serial" => config.serial_data = pin()?,
+                "clock" => config.clock = pin()?,
+                "read" => config.read = pin()?,
+                "write" => config.write = pin()?,
+                "data" => {
+                    let pins = value.split(',').map(|pin| parse_pin(pin, number)).collect::<Result<Vec<u8>>>()?;
+                    config.data = pins.try_into()
+                        .map_err(|_| Error::Config(format!("Line {}: expected 8 data pins", number + 1)))?;
+                },
+                "delay" => config.timing = BusTiming::parse(value)?,
+                other => return Err(Error::Config(format!("Line {}: unknown key {:?}", number + 1, other))),
+            }
+        }
+        config.check_unique()?;
+        Ok(config)
+    }
+
+    fn check_unique(&self) -> Result<()> {
+        let mut pins = vec![self.cs, self.latch, self.serial_data, self.clock, self.read, self.write];
+        pins.extend_from_slice(&self.data);
+        pins.sort_unstable();
+        match pins.windows(2).find(|pair| pair[0] == pair[1]) {
+            Some(pair) => Err(Error::Config(format!("GPIO {} is assigned twice", pair[0]))),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_pin(value: &str, line: usize) -> Result<u8> {
+    match value.trim().parse::<u8>() {
+        Ok(pin) if pin <= 27 => Ok(pin),
+        _ => Err(Error::Config(format!("Line {}: {:?} is not a GPIO number between 0 and 27", line + 1, value.trim()))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BusConfig, BusTiming};
+    use std::time::Duration;
+
+    #[test]
+    fn parses_board_revision() {
+        let config = BusConfig::parse("# rev 2\ncs = 17\ndata = 0, 1, 2, 3, 4, 5, 6, 7 \ndelay = auto\n").unwrap();
+        assert_eq!(config.cs, 17);
+        assert_eq!(config.latch, BusConfig::default().latch);
+        assert_eq!(config.data, [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(config.timing, BusTiming::Calibrate);
+        assert_eq!(BusConfig::parse("delay = 500").unwrap().timing, BusTiming::Fixed(Duration::from_nanos(500)));
+    }
+
+    #[test]
+    fn rejects_bad_config() {
+        assert!(BusConfig::parse("cs = 40").is_err());
+        assert!(BusConfig::parse("data = 1, 2, 3").is_err());
+        assert!(BusConfig::parse("chip_select = 18").is_err());
+        // 18 is the default chip select pin
+        assert!(BusConfig::parse("latch = 18").is_err());
+    }
+}
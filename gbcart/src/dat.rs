@@ -0,0 +1,186 @@
+//! Identifies ROM images with a No-Intro or Redump style XML DAT file.
+
+use crate::error::{Error, Result};
+use std::fmt;
+use std::path::Path;
+
+/// The checksums DAT files list for each ROM
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomHashes {
+    pub size: usize,
+    pub crc32: u32,
+    pub sha1: String,
+}
+
+impl RomHashes {
+    pub fn of(data: &[u8]) -> RomHashes {
+        RomHashes {
+            size: data.len(),
+            crc32: crc32fast::hash(data),
+            sha1: sha1_smol::Sha1::from(data).digest().to_string(),
+        }
+    }
+}
+
+/// A ROM listed in the DAT file
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatEntry {
+    pub game: String,
+    /// The canonical file name of the ROM
+    pub rom_name: String,
+    pub size: usize,
+    pub crc32: u32,
+    pub sha1: Option<String>,
+    /// The only known dump of the game is marked as bad
+    pub bad_dump: bool,
+}
+
+impl DatEntry {
+    fn matches(&self, hashes: &RomHashes) -> bool {
+        self.size == hashes.size && match self.sha1 {
+            Some(ref sha1) => sha1.eq_ignore_ascii_case(&hashes.sha1),
+            None => self.crc32 == hashes.crc32,
+        }
+    }
+}
+
+/// The result of looking up a ROM image
+#[derive(Debug, PartialEq)]
+pub enum DatMatch<'a> {
+    Verified(&'a DatEntry),
+    BadDump(&'a DatEntry),
+    /// The image starts with a known ROM, but is larger than it
+    Overdump(&'a DatEntry),
+    Unknown,
+}
+
+impl<'a> DatMatch<'a> {
+    pub fn entry(&self) -> Option<&'a DatEntry> {
+        match *self {
+            DatMatch::Verified(entry) | DatMatch::BadDump(entry) | DatMatch::Overdump(entry) => Some(entry),
+            DatMatch::Unknown => None,
+        }
+    }
+}
+
+impl<'a> fmt::Display for DatMatch<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DatMatch::Verified(entry) => write!(f, "Verified good dump: {}", entry.game),
+            DatMatch::BadDump(entry) => write!(f, "Matches a known bad dump: {}", entry.game),
+            DatMatch::Overdump(entry) => write!(f, "Overdump of {}, only the first {} bytes belong to the game", entry.game, entry.size),
+            DatMatch::Unknown => write!(f, "Not found in the DAT file"),
+        }
+    }
+}
+
+/// The ROMs listed in a DAT file
+#[derive(Clone, Debug, Default)]
+pub struct Dat {
+    entries: Vec<DatEntry>,
+}
+
+impl Dat {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Dat> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::Dat(format!("Could not read {}: {}", path.display(), e)))?;
+        Dat::parse(&text)
+    }
+
+    pub fn parse(xml: &str) -> Result<Dat> {
+        // Real DATs name the Logiqx DTD in their DOCTYPE
+        let options = roxmltree::ParsingOptions { allow_dtd: true, ..Default::default() };
+        let document = roxmltree::Document::parse_with_options(xml, options).map_err(|e| Error::Dat(format!("Invalid DAT file: {}", e)))?;
+        let mut entries = Vec::new();
+        for game in document.descendants().filter(|node| node.has_tag_name("game") || node.has_tag_name("machine")) {
+            let game_name = game.attribute("name").unwrap_or_default();
+            for rom in game.children().filter(|node| node.has_tag_name("rom")) {
+                let (Some(size), Some(crc32)) = (rom.attribute("size"), rom.attribute("crc")) else {
+                    continue;
+                };
+                let size = size.parse::<usize>();
+                let crc32 = u32::from_str_radix(crc32, 16);
+                let (Ok(size), Ok(crc32)) = (size, crc32) else {
+                    return Err(Error::Dat(format!("Invalid size or CRC for {}", game_name)));
+                };
+                entries.push(DatEntry {
+                    game: game_name.to_owned(),
+                    rom_name: rom.attribute("name").unwrap_or(game_name).to_owned(),
+                    size,
+                    crc32,
+                    sha1: rom.attribute("sha1").map(|sha1| sha1.to_ascii_lowercase()),
+                    bad_dump: rom.attribute("status") == Some("baddump"),
+                });
+            }
+        }
+        Ok(Dat { entries })
+    }
+
+    pub fn entries(&self) -> &[DatEntry] {
+        &self.entries
+    }
+
+    pub fn lookup(&self, data: &[u8]) -> DatMatch<'_> {
+        let hashes = RomHashes::of(data);
+        if let Some(entry) = self.entries.iter().find(|entry| entry.matches(&hashes)) {
+            return if entry.bad_dump { DatMatch::BadDump(entry) } else { DatMatch::Verified(entry) };
+        }
+        // An overdump repeats the ROM or pads it up to the size of the chip it was read as
+        let mut sizes: Vec<usize> = self.entries.iter().map(|entry| entry.size).filter(|&size| size > 0 && size < data.len()).collect();
+        sizes.sort_unstable();
+        sizes.dedup();
+        for size in sizes.into_iter().rev() {
+            let hashes = RomHashes::of(&data[..size]);
+            if let Some(entry) = self.entries.iter().find(|entry| entry.matches(&hashes)) {
+                return DatMatch::Overdump(entry);
+            }
+        }
+        DatMatch::Unknown
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Dat, DatMatch, RomHashes};
+
+    fn dat_for(roms: &[(&str, &[u8], &str)]) -> Dat {
+        let games: String = roms.iter().map(|(name, data, status)| {
+            let hashes = RomHashes::of(data);
+            format!(r#"<game name="{0}"><description>{0}</description><rom name="{0}.gb" size="{1}" crc="{2:08X}" sha1="{3}" {4}/></game>"#,
+                    name, hashes.size, hashes.crc32, hashes.sha1.to_uppercase(), status)
+        }).collect();
+        Dat::parse(&format!(concat!(r#"<?xml version="1.0"?>"#,
+            r#"<!DOCTYPE datafile PUBLIC "-//Logiqx//DTD ROM Management Datafile//EN" "http://www.logiqx.com/Dats/datafile.dtd">"#,
+            r#"<datafile><header><name>Test</name></header>{}</datafile>"#), games)).unwrap()
+    }
+
+    #[test]
+    fn hashes_known_values() {
+        let hashes = RomHashes::of(b"abc");
+        assert_eq!(hashes.crc32, 0x352441C2);
+        assert_eq!(hashes.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn identifies_dumps() {
+        let good = vec![0x11; 0x8000];
+        let bad = vec![0x22; 0x8000];
+        let dat = dat_for(&[("Good Game (World)", &good, ""), ("Bad Game (Japan)", &bad, r#"status="baddump""#)]);
+        assert_eq!(dat.entries().len(), 2);
+        assert_eq!(dat.lookup(&good).entry().unwrap().rom_name, "Good Game (World).gb");
+        assert!(matches!(dat.lookup(&good), DatMatch::Verified(_)));
+        assert!(matches!(dat.lookup(&bad), DatMatch::BadDump(_)));
+
+        let mut overdump = good.clone();
+        overdump.extend_from_slice(&good);
+        assert!(matches!(dat.lookup(&overdump), DatMatch::Overdump(entry) if entry.game == "Good Game (World)"));
+        assert_eq!(dat.lookup(&[0x33; 0x8000]), DatMatch::Unknown);
+    }
+
+    #[test]
+    fn rejects_invalid_dat() {
+        assert!(Dat::parse("<datafile><game name=\"x\"><rom size=\"big\" crc=\"0\"/></game></datafile>").is_err());
+        assert!(Dat::parse("not xml").is_err());
+    }
+}
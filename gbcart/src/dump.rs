@@ -0,0 +1,77 @@
+use crate::bus::{read_rom_bank, write_byte};
+use crate::header::{global_checksum, header_checksum, CartridgeHeader};
+use crate::pins::CartridgePins;
+
+/// Number of extra reads of a bank before it is reported as unstable
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// The result of checking a dump against its checksums and against re-reads of each bank
+#[derive(Debug, Default)]
+pub struct DumpReport {
+    pub header_checksum_ok: bool,
+    pub global_checksum_ok: bool,
+    /// Banks which only matched a re-read after retrying
+    pub retried_banks: Vec<u16>,
+    /// Banks which never read the same twice in a row
+    pub unstable_banks: Vec<u16>,
+}
+
+impl DumpReport {
+    pub fn passed(&self) -> bool {
+        self.header_checksum_ok && self.global_checksum_ok && self.unstable_banks.is_empty()
+    }
+
+    pub fn print(&self) {
+        let status = |ok: bool| if ok { "OK" } else { "MISMATCH" };
+        println!("Header checksum: {}", status(self.header_checksum_ok));
+        println!("Global checksum: {}", status(self.global_checksum_ok));
+        if !self.retried_banks.is_empty() {
+            println!("Banks recovered after retrying: {:?}", self.retried_banks);
+        }
+        if !self.unstable_banks.is_empty() {
+            println!("Banks that never read consistently: {:?}", self.unstable_banks);
+        }
+        println!("Verification {}", if self.passed() { "PASSED" } else { "FAILED" });
+    }
+}
+
+/// Dumps the ROM, reading every bank until two reads in a row agree
+pub fn dump_rom<P: CartridgePins + ?Sized>(pins: &mut P, retries: u32) -> (Vec<u8>, DumpReport) {
+    let mut report = DumpReport::default();
+    let mut data: Vec<u8> = Vec::new();
+    // Read the data from bank 0
+    data.extend(read_stable_bank(pins, 0, 0, retries, &mut report));
+    let header = CartridgeHeader::from_bytes(&data).expect("bank 0 holds the whole header");
+    let banks_count = header.rom_banks();
+    let mapper = header.mapper();
+    eprintln!("Cartridge: {}, {}", header.title, header.cartridge_type_name());
+    // Disable the RAM, so nothing can corrupt the save while banks are switched
+    write_byte(pins, 0x0000, 0);
+    eprintln!("Banks count: {}", banks_count);
+    for bank in 1..banks_count {
+        eprintln!("Reading bank {}..", bank);
+        let start_address = mapper.select_rom_bank(pins, bank);
+        data.extend(read_stable_bank(pins, bank, start_address, retries, &mut report));
+    }
+    mapper.reset_banking(pins);
+    report.header_checksum_ok = header_checksum(&data) == data[0x014D];
+    report.global_checksum_ok = global_checksum(&data) == header.global_checksum;
+    (data, report)
+}
+
+fn read_stable_bank<P: CartridgePins + ?Sized>(pins: &mut P, bank: u16, start_address: u16, retries: u32, report: &mut DumpReport) -> Vec<u8> {
+    let mut previous = read_rom_bank(pins, start_address);
+    for attempt in 0..=retries {
+        let current = read_rom_bank(pins, start_address);
+        if current == previous {
+            if attempt > 0 {
+                report.retried_banks.push(bank);
+            }
+            return current;
+        }
+        eprintln!("Bank {} differs between reads, retrying..", bank);
+        previous = current;
+    }
+    report.unstable_banks.push(bank);
+    previous
+}
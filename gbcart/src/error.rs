@@ -0,0 +1,50 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The GPIO interface could not be opened
+    Gpio(rppal::gpio::Error),
+    /// The adapter config file is invalid
+    Config(String),
+    /// The header reads back corrupt, so there is no cartridge or it does not make contact
+    NoCartridge,
+    /// The cartridge type has no save RAM
+    NoSaveRam(u8),
+    /// The RAM size code in the header is not known
+    UnknownRamSize(u8),
+    /// A save file does not fit the RAM of the cartridge
+    SaveSizeMismatch { expected: usize, actual: usize },
+    /// A ROM image can not be programmed onto a flash cartridge
+    InvalidImage(usize),
+    /// The DAT file can not be read
+    Dat(String),
+    /// The flash chip did not finish an erase or program operation
+    Flash(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Gpio(e) => write!(f, "Could not open the GPIO cartridge interface: {}", e),
+            Error::Config(message) => write!(f, "{}", message),
+            Error::NoCartridge => write!(f, "No cartridge detected or the cartridge header is corrupt"),
+            Error::NoSaveRam(cartridge_type) => write!(f, "Cartridge type {:#04x} has no save RAM", cartridge_type),
+            Error::UnknownRamSize(ram_size) => write!(f, "Unknown RAM size {:#04x}", ram_size),
+            Error::SaveSizeMismatch { expected, actual } =>
+                write!(f, "Save file has {} bytes, but the cartridge has {} bytes of RAM", actual, expected),
+            Error::InvalidImage(len) => write!(f, "A ROM image of {} bytes is not made of whole 16 KiB banks", len),
+            Error::Dat(message) => write!(f, "{}", message),
+            Error::Flash(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<rppal::gpio::Error> for Error {
+    fn from(e: rppal::gpio::Error) -> Error {
+        Error::Gpio(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
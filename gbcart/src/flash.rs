@@ -0,0 +1,208 @@
+use std::time::{Duration, Instant};
+use crate::bus::{read_byte, read_rom_bank, write_byte, BANK_SIZE};
+use crate::error::{Error, Result};
+use crate::mapper::Mapper;
+use crate::pins::CartridgePins;
+
+const BANK_WINDOW: u16 = 0x4000;
+const PROGRAM_TIMEOUT: Duration = Duration::from_millis(100);
+const ERASE_TIMEOUT: Duration = Duration::from_secs(180);
+// Set by the chip when an embedded operation exceeded its internal time limit
+const DQ5_TIMEOUT: u8 = 0x20;
+
+/// The JEDEC command addressing used by a flash cartridge
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FlashVariant {
+    /// AM29F016 and compatibles, commands at 0x555/0x2AA
+    AM29F016,
+    /// 29LV chips in byte mode, commands at 0xAAA/0x555
+    LV29,
+    /// 29LV chips in byte mode with data lines D0 and D1 swapped on the cartridge
+    LV29Swapped,
+}
+
+impl FlashVariant {
+    pub fn from_name(name: &str) -> Option<FlashVariant> {
+        match name {
+            "29f016" => Some(FlashVariant::AM29F016),
+            "29lv" => Some(FlashVariant::LV29),
+            "29lv-swapped" => Some(FlashVariant::LV29Swapped),
+            _ => None,
+        }
+    }
+
+    fn unlock_addresses(self) -> (u16, u16) {
+        match self {
+            FlashVariant::AM29F016 => (0x555, 0x2AA),
+            FlashVariant::LV29 | FlashVariant::LV29Swapped => (0xAAA, 0x555),
+        }
+    }
+
+    fn encode(self, value: u8) -> u8 {
+        match self {
+            FlashVariant::LV29Swapped => (value & 0xFC) | ((value & 0x01) << 1) | ((value & 0x02) >> 1),
+            _ => value,
+        }
+    }
+}
+
+/// Programs reproduction flash cartridges with an MBC5-style mapper
+pub struct FlashCart {
+    variant: FlashVariant,
+}
+
+impl FlashCart {
+    pub fn new(variant: FlashVariant) -> FlashCart {
+        FlashCart { variant }
+    }
+
+    fn command<P: CartridgePins + ?Sized>(&self, pins: &mut P, command: u8) {
+        let (first, second) = self.variant.unlock_addresses();
+        write_byte(pins, first, self.variant.encode(0xAA));
+        write_byte(pins, second, self.variant.encode(0x55));
+        write_byte(pins, first, self.variant.encode(command));
+    }
+
+    fn reset<P: CartridgePins + ?Sized>(&self, pins: &mut P) {
+        write_byte(pins, 0x0000, self.variant.encode(0xF0));
+    }
+
+    /// Reads the JEDEC manufacturer and device id
+    pub fn identify<P: CartridgePins + ?Sized>(&self, pins: &mut P) -> (u8, u8) {
+        self.command(pins, 0x90);
+        let manufacturer = read_byte(pins, 0x0000);
+        let device = read_byte(pins, if self.variant == FlashVariant::AM29F016 { 0x0001 } else { 0x0002 });
+        self.reset(pins);
+        // Swapping the data lines back is the same as swapping them
+        (self.variant.encode(manufacturer), self.variant.encode(device))
+    }
+
+    pub fn erase_chip<P: CartridgePins + ?Sized>(&self, pins: &mut P) -> Result<()> {
+        self.command(pins, 0x80);
+        self.command(pins, 0x10);
+        let result = wait_for(pins, 0x0000, 0xFF, ERASE_TIMEOUT);
+        self.reset(pins);
+        result.map_err(|e| Error::Flash(format!("Chip erase failed: {}", e)))
+    }
+
+    /// Programs `rom` bank by bank through the 0x4000-0x7FFF window. The chip must be erased.
+    pub fn program<P: CartridgePins + ?Sized>(&self, pins: &mut P, rom: &[u8]) -> Result<()> {
+        if rom.is_empty() || !rom.len().is_multiple_of(BANK_SIZE) || rom.len() > 512 * BANK_SIZE {
+            return Err(Error::InvalidImage(rom.len()));
+        }
+        for (bank, data) in rom.chunks(BANK_SIZE).enumerate() {
+            eprintln!("Programming bank {}..", bank);
+            Mapper::MBC5.select_rom_bank(pins, bank as u16);
+            for (offset, &value) in data.iter().enumerate() {
+                if value == 0xFF {
+                    // Erased flash already reads 0xFF
+                    continue;
+                }
+                let address = BANK_WINDOW + offset as u16;
+                self.command(pins, 0xA0);
+                write_byte(pins, address, value);
+                if let Err(e) = wait_for(pins, address, value, PROGRAM_TIMEOUT) {
+                    self.reset(pins);
+                    return Err(Error::Flash(format!("Programming bank {} at {:#06x} failed: {}", bank, address, e)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the cartridge back and returns the banks which differ from `rom`
+    pub fn verify<P: CartridgePins + ?Sized>(&self, pins: &mut P, rom: &[u8]) -> Vec<u16> {
+        let mut bad_banks = Vec::new();
+        for (bank, data) in rom.chunks(BANK_SIZE).enumerate() {
+            eprintln!("Verifying bank {}..", bank);
+            Mapper::MBC5.select_rom_bank(pins, bank as u16);
+            if read_rom_bank(pins, BANK_WINDOW)[..data.len()] != *data {
+                bad_banks.push(bank as u16);
+            }
+        }
+        Mapper::MBC5.select_rom_bank(pins, 1);
+        bad_banks
+    }
+}
+
+/// Uses data polling: DQ7 reads inverted until the embedded operation has finished
+fn wait_for<P: CartridgePins + ?Sized>(pins: &mut P, address: u16, expected: u8, timeout: Duration) -> std::result::Result<(), String> {
+    let start = Instant::now();
+    loop {
+        let value = read_byte(pins, address);
+        if value == expected {
+            return Ok(());
+        }
+        if value & DQ5_TIMEOUT != 0 && read_byte(pins, address) != expected {
+            return Err("the chip reported a timeout".to_owned());
+        }
+        if start.elapsed() > timeout {
+            return Err(format!("no completion after {:?}", timeout));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FlashCart, FlashVariant};
+    use crate::error::Error;
+    use crate::simulator::{test_rom, FlashModel, SimulatedBus, FLASH_DEVICE, FLASH_MANUFACTURER};
+
+    const VARIANTS: [FlashVariant; 3] = [FlashVariant::AM29F016, FlashVariant::LV29, FlashVariant::LV29Swapped];
+
+    fn flash_bus(variant: FlashVariant) -> SimulatedBus<FlashModel> {
+        SimulatedBus::new(FlashModel::new(variant, 0x10000))
+    }
+
+    #[test]
+    fn unlocks_each_variant() {
+        for chip in VARIANTS {
+            for variant in VARIANTS {
+                let ids = FlashCart::new(variant).identify(&mut flash_bus(chip));
+                // A command sequence for other wiring reads the blank chip instead
+                let expected = if variant == chip { (FLASH_MANUFACTURER, FLASH_DEVICE) } else { (0x00, 0x00) };
+                assert_eq!(ids, expected, "{:?} on a {:?} chip", variant, chip);
+            }
+        }
+    }
+
+    #[test]
+    fn erases_programs_and_verifies() {
+        for variant in VARIANTS {
+            let rom = test_rom(0x19, 0x01, 0x00);
+            let cart = FlashCart::new(variant);
+            let mut bus = flash_bus(variant);
+            cart.erase_chip(&mut bus).unwrap();
+            assert!(bus.cartridge().flash.iter().all(|&v| v == 0xFF));
+
+            cart.program(&mut bus, &rom).unwrap();
+            assert!(cart.verify(&mut bus, &rom).is_empty(), "{:?} programmed the wrong data", variant);
+        }
+    }
+
+    #[test]
+    fn verify_reports_bad_banks() {
+        let mut rom = test_rom(0x19, 0x01, 0x00);
+        let cart = FlashCart::new(FlashVariant::LV29);
+        let mut bus = flash_bus(FlashVariant::LV29);
+        cart.erase_chip(&mut bus).unwrap();
+        cart.program(&mut bus, &rom).unwrap();
+        rom[0x4000 * 2 + 0x123] ^= 0x01;
+        assert_eq!(cart.verify(&mut bus, &rom), vec![2]);
+    }
+
+    #[test]
+    fn reports_chip_timeout() {
+        let rom = test_rom(0x19, 0x01, 0x00);
+        let cart = FlashCart::new(FlashVariant::AM29F016);
+        let mut bus = SimulatedBus::new(FlashModel::new(FlashVariant::AM29F016, 0x10000).with_timeout());
+        match cart.erase_chip(&mut bus) {
+            Err(Error::Flash(message)) => assert_eq!(message, "Chip erase failed: the chip reported a timeout"),
+            other => panic!("Erase did not time out: {:?}", other),
+        }
+        match cart.program(&mut bus, &rom) {
+            Err(Error::Flash(message)) => assert_eq!(message, "Programming bank 0 at 0x4000 failed: the chip reported a timeout"),
+            other => panic!("Programming did not time out: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use crate::mapper::Mapper;
+use std::fmt;
+
+/// Size of the area which holds the header, counted from address 0
+pub const HEADER_END: usize = 0x150;
+
+/// The logo at 0x0104-0x0133, which the boot ROM compares before starting the game
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// Whether the game uses the Game Boy Color features, from byte 0x143
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CgbSupport {
+    None,
+    /// Also runs on the original Game Boy
+    Compatible,
+    Only,
+}
+
+impl CgbSupport {
+    pub fn name(self) -> &'static str {
+        match self {
+            CgbSupport::None => "none",
+            CgbSupport::Compatible => "compatible",
+            CgbSupport::Only => "only",
+        }
+    }
+}
+
+/// The market the cartridge was sold in, from byte 0x14A
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+impl Destination {
+    pub fn name(self) -> &'static str {
+        match self {
+            Destination::Japan => "Japan",
+            Destination::Overseas => "overseas",
+        }
+    }
+}
+
+/// The cartridge header at 0x0100-0x014F
+#[derive(Clone, Debug, PartialEq)]
+pub struct CartridgeHeader {
+    pub logo_ok: bool,
+    pub title: String,
+    /// Four letter code of newer games, stored in the last bytes of the title area
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub destination: Destination,
+    /// The licensee code at 0x14B, 0x33 means the new code at 0x144 is used instead
+    pub old_licensee: u8,
+    pub new_licensee: Option<String>,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_checksum: u8,
+    /// Only known when the header was parsed from a whole ROM
+    global_checksum_ok: Option<bool>,
+}
+
+impl CartridgeHeader {
+    /// Parses the header from the first 0x150 bytes of the ROM
+    pub fn from_bytes(data: &[u8]) -> Option<CartridgeHeader> {
+        if data.len() < HEADER_END {
+            return None;
+        }
+        let cgb = match data[0x143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+        // Games with the CGB flag only have 11 characters of title, as the last ones may be
+        // the manufacturer code
+        let title_len = if cgb == CgbSupport::None { 16 } else { 11 };
+        let title = data[0x134..0x134 + title_len].iter()
+            .take_while(|&&v| v != 0)
+            .map(|&v| v as char)
+            .collect();
+        let code = &data[0x13F..0x143];
+        let manufacturer_code = if cgb != CgbSupport::None && code.iter().all(|v| v.is_ascii_uppercase() || v.is_ascii_digit()) {
+            Some(code.iter().map(|&v| v as char).collect())
+        } else {
+            None
+        };
+        let new_licensee = if data[0x14B] == 0x33 {
+            Some(data[0x144..0x146].iter().map(|&v| v as char).collect())
+        } else {
+            None
+        };
+        Some(CartridgeHeader {
+            logo_ok: data[0x104..0x134] == NINTENDO_LOGO,
+            title,
+            manufacturer_code,
+            cgb,
+            sgb: data[0x146] == 0x03,
+            cartridge_type: data[0x147],
+            rom_size: data[0x148],
+            ram_size: data[0x149],
+            destination: if data[0x14A] == 0 { Destination::Japan } else { Destination::Overseas },
+            old_licensee: data[0x14B],
+            new_licensee,
+            version: data[0x14C],
+            header_checksum: data[0x14D],
+            global_checksum: (data[0x14E] as u16) << 8 | data[0x14F] as u16,
+            computed_checksum: header_checksum(data),
+            global_checksum_ok: None,
+        })
+    }
+
+    /// Parses the header of a whole ROM image, which also allows checking the global checksum
+    pub fn from_rom(rom: &[u8]) -> Option<CartridgeHeader> {
+        let mut header = CartridgeHeader::from_bytes(rom)?;
+        header.global_checksum_ok = Some(global_checksum(rom) == header.global_checksum);
+        Some(header)
+    }
+
+    pub fn mapper(&self) -> Mapper {
+        Mapper::from_cartridge_type(self.cartridge_type)
+    }
+
+    pub fn cartridge_type_name(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 => "ROM ONLY",
+            0x01 => "MBC1",
+            0x02 => "MBC1+RAM",
+            0x03 => "MBC1+RAM+BATTERY",
+            0x05 => "MBC2",
+            0x06 => "MBC2+BATTERY",
+            0x08 => "ROM+RAM",
+            0x09 => "ROM+RAM+BATTERY",
+            0x0B => "MMM01",
+            0x0C => "MMM01+RAM",
+            0x0D => "MMM01+RAM+BATTERY",
+            0x0F => "MBC3+TIMER+BATTERY",
+            0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
+            0x19 => "MBC5",
+            0x1A => "MBC5+RAM",
+            0x1B => "MBC5+RAM+BATTERY",
+            0x1C => "MBC5+RUMBLE",
+            0x1D => "MBC5+RUMBLE+RAM",
+            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+            0x20 => "MBC6",
+            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+            0xFC => "POCKET CAMERA",
+            0xFD => "BANDAI TAMA5",
+            0xFE => "HuC3",
+            0xFF => "HuC1+RAM+BATTERY",
+            _ => "unknown",
+        }
+    }
+
+    /// Number of 16 KiB ROM banks, 0 if the size code is unknown
+    pub fn rom_banks(&self) -> u16 {
+        rom_banks(self.rom_size) as u16
+    }
+
+    /// Size of the ROM in bytes
+    pub fn rom_bytes(&self) -> usize {
+        rom_banks(self.rom_size) * 0x4000
+    }
+
+    /// Size of the save RAM in bytes, including the RAM built into MBC2
+    pub fn ram_bytes(&self) -> usize {
+        match (self.cartridge_type, self.ram_size) {
+            (0x05 | 0x06, _) => 512,
+            (_, 1) => 0x800,
+            (_, code) => ram_banks(code) * 0x2000,
+        }
+    }
+
+    /// Whether the checksum of 0x0134-0x014C matches 0x014D. The boot ROM refuses to start otherwise,
+    /// so a mismatch usually means that no cartridge is inserted or it is read badly.
+    pub fn checksum_ok(&self) -> bool {
+        self.computed_checksum == self.header_checksum
+    }
+
+    /// Whether the sum of the whole ROM matches 0x014E-0x014F, if the whole ROM was available
+    pub fn global_checksum_ok(&self) -> Option<bool> {
+        self.global_checksum_ok
+    }
+
+    /// The licensee code as printed in the docs, two letters for the new code and hex for the old one
+    pub fn licensee_code(&self) -> String {
+        match self.new_licensee {
+            Some(ref code) => code.clone(),
+            None => format!("{:02X}", self.old_licensee),
+        }
+    }
+
+    pub fn licensee(&self) -> Option<&'static str> {
+        match self.new_licensee {
+            Some(ref code) => new_licensee_name(code),
+            None => old_licensee_name(self.old_licensee),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let value = serde_json::json!({
+            "title": self.title,
+            "manufacturer_code": self.manufacturer_code,
+            "cartridge_type": self.cartridge_type,
+            "cartridge_type_name": self.cartridge_type_name(),
+            "rom_size": self.rom_size,
+            "rom_bytes": self.rom_bytes(),
+            "rom_banks": self.rom_banks(),
+            "ram_size": self.ram_size,
+            "ram_bytes": self.ram_bytes(),
+            "cgb": self.cgb.name(),
+            "sgb": self.sgb,
+            "licensee_code": self.licensee_code(),
+            "licensee": self.licensee(),
+            "destination": self.destination.name(),
+            "version": self.version,
+            "logo_ok": self.logo_ok,
+            "header_checksum": self.header_checksum,
+            "header_checksum_ok": self.checksum_ok(),
+            "global_checksum": self.global_checksum,
+            "global_checksum_ok": self.global_checksum_ok,
+        });
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = |ok: bool| if ok { "OK" } else { "MISMATCH" };
+        writeln!(f, "Title:           {}", self.title)?;
+        if let Some(ref code) = self.manufacturer_code {
+            writeln!(f, "Manufacturer:    {}", code)?;
+        }
+        writeln!(f, "Cartridge type:  {:#04x} {}", self.cartridge_type, self.cartridge_type_name())?;
+        writeln!(f, "ROM size:        {} KiB ({} banks)", self.rom_bytes() / 1024, self.rom_banks())?;
+        match self.ram_bytes() {
+            0 => writeln!(f, "RAM size:        none")?,
+            bytes if bytes < 1024 => writeln!(f, "RAM size:        {} bytes", bytes)?,
+            bytes => writeln!(f, "RAM size:        {} KiB", bytes / 1024)?,
+        }
+        writeln!(f, "CGB support:     {}", self.cgb.name())?;
+        writeln!(f, "SGB support:     {}", if self.sgb { "yes" } else { "no" })?;
+        writeln!(f, "Licensee:        {} ({})", self.licensee().unwrap_or("unknown"), self.licensee_code())?;
+        writeln!(f, "Destination:     {}", self.destination.name())?;
+        writeln!(f, "Version:         {}", self.version)?;
+        writeln!(f, "Nintendo logo:   {}", status(self.logo_ok))?;
+        writeln!(f, "Header checksum: {:#04x} {}", self.header_checksum, status(self.checksum_ok()))?;
+        match self.global_checksum_ok {
+            Some(ok) => write!(f, "Global checksum: {:#06x} {}", self.global_checksum, status(ok)),
+            None => write!(f, "Global checksum: {:#06x}", self.global_checksum),
+        }
+    }
+}
+
+/// Number of 16 KiB ROM banks for the size code at 0x148, 0 if the code is unknown
+pub fn rom_banks(code: u8) -> usize {
+    match code {
+        0x00 ..= 0x08 => 2 << code,
+        0x52 => 72,
+        0x53 => 80,
+        0x54 => 96,
+        _ => 0,
+    }
+}
+
+/// Number of 8 KiB RAM banks for the size code at 0x149
+pub fn ram_banks(code: u8) -> usize {
+    match code {
+        1 =>
+            // "Listed in various unofficial docs as 2 KiB. However, a 2 KiB RAM chip was never
+            // used in a cartridge. The source of this value is unknown."
+            // Needed by some test roms. As we only deal in whole banks, just make it 1 8KiB bank.
+            1,
+        2 => 1,
+        3 => 4,
+        4 => 16,
+        5 => 8,
+        _ => 0,
+    }
+}
+
+pub fn header_checksum(data: &[u8]) -> u8 {
+    data[0x0134..0x014D].iter().fold(0u8, |sum, &v| sum.wrapping_sub(v).wrapping_sub(1))
+}
+
+/// Sum of all ROM bytes except the checksum itself
+pub fn global_checksum(data: &[u8]) -> u16 {
+    data.iter().enumerate()
+        .filter(|&(address, _)| address != 0x014E && address != 0x014F)
+        .fold(0u16, |sum, (_, &v)| sum.wrapping_add(v as u16))
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x00 => "none",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "Hot-B",
+        0x0A | 0xE0 => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C | 0x6E => "Elite Systems",
+        0x13 | 0x69 => "Electronic Arts",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F | 0x4A | 0x61 => "Virgin Games",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "Seta",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xA2 | 0xB2 => "Bandai",
+        0x34 | 0xA4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9D | 0xD9 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xEB => "Atlus",
+        0x44 | 0x4D => "Malibu Interactive",
+        0x46 | 0xCF => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xB0 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xDB | 0xFF => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C | 0xD6 => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x67 => "Ocean Software",
+        0x6F => "Electro Brain",
+        0x71 => "Interplay Entertainment",
+        0x72 | 0xAA => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x7F | 0x97 | 0xC2 => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC",
+        0x86 | 0xC4 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai",
+        0x8E => "Ape",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 | 0xE3 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB1 => "ASCII/Nexsoft",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 | 0xCE => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy",
+        0xC0 | 0xD0 => "Taito",
+        0xC3 => "Square",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra",
+        0xCB => "Vap",
+        0xCC => "Use",
+        0xCD => "Meldac",
+        0xD1 => "Sofel",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha",
+        0xD7 => "Copya System",
+        0xDA => "Tomy",
+        0xDD => "NCS",
+        0xDE => "Human",
+        0xDF => "Altron",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        _ => return None,
+    })
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    Some(match code {
+        "00" => "none",
+        "01" | "31" => "Nintendo",
+        "08" => "Capcom",
+        "13" | "69" => "Electronic Arts",
+        "18" | "38" => "Hudson Soft",
+        "19" => "B-AI",
+        "20" => "KSS",
+        "22" => "Planning Office WADA",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco",
+        "29" => "Seta",
+        "30" => "Viacom",
+        "32" => "Bandai",
+        "33" | "93" => "Ocean Software/Acclaim Entertainment",
+        "34" | "54" | "A4" => "Konami",
+        "35" => "HectorSoft",
+        "37" => "Taito",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu Interactive",
+        "46" => "Angel",
+        "47" => "Bullet-Proof Software",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim Entertainment",
+        "52" => "Activision",
+        "53" => "Sammy USA",
+        "55" => "Hi Tech Expressions",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley",
+        "60" => "Titus Interactive",
+        "61" => "Virgin Games",
+        "64" => "Lucasfilm Games",
+        "67" => "Ocean Software",
+        "70" => "Infogrames",
+        "71" => "Interplay Entertainment",
+        "72" => "Broderbund",
+        "73" => "Sculptured Software",
+        "75" => "The Sales Curve",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa Entertainment",
+        "83" => "LOZC",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft",
+        "92" => "Video System",
+        "95" => "Varie",
+        "96" => "Yonezawa/S'Pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        "9H" => "Bottom Up",
+        "BL" => "MTO",
+        "DK" => "Kodansha",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CartridgeHeader, CgbSupport, Destination, NINTENDO_LOGO};
+    use crate::simulator::test_rom;
+
+    #[test]
+    fn parses_cgb_header() {
+        let mut rom = test_rom(0x1B, 0x05, 0x03);
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x134..0x143].copy_from_slice(b"POKEMON_SLVAAXE");
+        rom[0x143] = 0x80;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x146] = 0x03;
+        rom[0x14A] = 0x01;
+        rom[0x14B] = 0x33;
+        rom[0x14C] = 0x02;
+        let header = CartridgeHeader::from_bytes(&rom).unwrap();
+        assert!(header.logo_ok);
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code.as_deref(), Some("AAXE"));
+        assert_eq!(header.cgb, CgbSupport::Compatible);
+        assert!(header.sgb);
+        assert_eq!(header.licensee(), Some("Nintendo"));
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 2);
+        assert_eq!(header.rom_bytes(), 1024 * 1024);
+        assert_eq!(header.ram_bytes(), 32 * 1024);
+        assert_eq!(header.cartridge_type_name(), "MBC5+RAM+BATTERY");
+        // The title changed after the checksums were calculated
+        assert!(!header.checksum_ok());
+        assert_eq!(header.global_checksum_ok(), None);
+    }
+
+    #[test]
+    fn checks_whole_rom() {
+        let rom = test_rom(0x06, 0x01, 0x00);
+        let header = CartridgeHeader::from_rom(&rom).unwrap();
+        assert_eq!(header.title, "SIMULATE");
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert!(!header.logo_ok);
+        assert_eq!(header.licensee_code(), "00");
+        assert_eq!(header.ram_bytes(), 512);
+        assert!(header.checksum_ok());
+        assert_eq!(header.global_checksum_ok(), Some(true));
+        let json: serde_json::Value = serde_json::from_str(&header.to_json()).unwrap();
+        assert_eq!(json["cartridge_type_name"], "MBC2+BATTERY");
+        assert_eq!(json["global_checksum_ok"], true);
+        assert!(CartridgeHeader::from_bytes(&rom[..0x14F]).is_none());
+    }
+}
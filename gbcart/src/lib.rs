@@ -0,0 +1,267 @@
+//! Access to Game Boy cartridges through the Raspberry Pi GPIO adapter.
+//!
+//! The adapter shifts the 16 address lines out through two 74HC595 shift registers and connects the
+//! data, /RD, /WR and /CS lines of the cartridge directly to the GPIO header. `Cartridge` wraps the
+//! bus protocol and knows how to switch banks for the common mappers.
+
+mod bus;
+mod config;
+mod dat;
+mod dump;
+mod error;
+mod flash;
+mod header;
+mod mapper;
+mod pins;
+mod rtc;
+mod sram;
+#[cfg(any(test, feature = "testing"))]
+pub mod simulator;
+
+pub use crate::config::{BusConfig, BusTiming};
+pub use crate::dat::{Dat, DatEntry, DatMatch, RomHashes};
+pub use crate::dump::{DumpReport, DEFAULT_RETRIES};
+pub use crate::error::{Error, Result};
+pub use crate::flash::{FlashCart, FlashVariant};
+pub use crate::header::{global_checksum, header_checksum, ram_banks, rom_banks, CartridgeHeader, CgbSupport, Destination, HEADER_END, NINTENDO_LOGO};
+pub use crate::mapper::Mapper;
+pub use crate::pins::{CartridgePins, GpioPins};
+pub use crate::rtc::{advance_rtc, rtc_seconds, RtcFooter, RTC_FOOTER_LEN};
+pub use crate::sram::SaveRam;
+
+use std::time::Duration;
+
+/// The delay calibration starts from, long enough for any board
+const CALIBRATION_START: Duration = Duration::from_micros(10);
+/// Shorter delays are not worth measuring, the GPIO writes themselves take longer
+const CALIBRATION_MIN: Duration = Duration::from_nanos(20);
+/// Reads of the header which have to agree at each delay
+const CALIBRATION_READS: usize = 4;
+
+/// A cartridge in the adapter
+pub struct Cartridge<P: CartridgePins> {
+    pins: P,
+    delay: Duration,
+}
+
+impl Cartridge<GpioPins> {
+    /// Opens the GPIO interface of the Raspberry Pi
+    pub fn open() -> Result<Cartridge<GpioPins>> {
+        Cartridge::open_with(&BusConfig::default())
+    }
+
+    /// Opens the pins given in `config`, calibrating the bus delay if asked to. The delay in use
+    /// is left to the caller to report, see `delay`.
+    pub fn open_with(config: &BusConfig) -> Result<Cartridge<GpioPins>> {
+        let mut cartridge = Cartridge::new(GpioPins::with_config(config)?);
+        if config.timing == BusTiming::Calibrate {
+            cartridge.calibrate()?;
+        }
+        Ok(cartridge)
+    }
+}
+
+impl<P: CartridgePins> Cartridge<P> {
+    /// Takes over `pins` and puts the bus in its idle state
+    pub fn new(mut pins: P) -> Cartridge<P> {
+        bus::idle_bus(&mut pins);
+        Cartridge { pins, delay: Duration::ZERO }
+    }
+
+    pub fn pins(&self) -> &P {
+        &self.pins
+    }
+
+    pub fn into_pins(self) -> P {
+        self.pins
+    }
+
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.pins.set_delay(delay);
+        self.delay = delay;
+    }
+
+    /// The bus delay last set or found by `calibrate`
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Finds the shortest bus delay at which the header reads back the same every time, and keeps
+    /// one step above it as a safety margin.
+    pub fn calibrate(&mut self) -> Result<Duration> {
+        self.set_delay(CALIBRATION_START);
+        let reference = self.read_header();
+        if !reference.checksum_ok() || (0..CALIBRATION_READS).any(|_| self.read_header() != reference) {
+            return Err(Error::NoCartridge);
+        }
+        let mut reliable = CALIBRATION_START;
+        loop {
+            let delay = if reliable / 2 < CALIBRATION_MIN { Duration::ZERO } else { reliable / 2 };
+            self.set_delay(delay);
+            if (0..CALIBRATION_READS).any(|_| self.read_header() != reference) {
+                break;
+            }
+            reliable = delay;
+            if delay.is_zero() {
+                break;
+            }
+        }
+        let delay = if reliable.is_zero() { reliable } else { (reliable * 2).min(CALIBRATION_START) };
+        self.set_delay(delay);
+        Ok(delay)
+    }
+
+    pub fn read_byte(&mut self, address: u16) -> u8 {
+        bus::read_byte(&mut self.pins, address)
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        bus::write_byte(&mut self.pins, address, value)
+    }
+
+    pub fn read_header(&mut self) -> CartridgeHeader {
+        let data: Vec<u8> = (0..HEADER_END as u16).map(|address| self.read_byte(address)).collect();
+        CartridgeHeader::from_bytes(&data).expect("the whole header was read")
+    }
+
+    /// Dumps the whole ROM, reading each bank until two reads agree, up to `retries` extra times
+    pub fn read_rom(&mut self, retries: u32) -> (Vec<u8>, DumpReport) {
+        dump::dump_rom(&mut self.pins, retries)
+    }
+
+    /// Reads the save RAM in the layout of rboy's `.gbsave` files, with the RTC footer of MBC3
+    pub fn read_ram(&mut self) -> Result<Vec<u8>> {
+        let save = SaveRam::from_header(&self.read_header())?;
+        Ok(save.dump(&mut self.pins))
+    }
+
+    /// Writes a `.gbsave` or raw `.sav` file to the save RAM
+    pub fn write_ram(&mut self, data: &[u8]) -> Result<()> {
+        let save = SaveRam::from_header(&self.read_header())?;
+        save.restore(&mut self.pins, data)
+    }
+
+    /// Erases a flash cartridge, programs `rom` onto it and returns the banks which read back wrong
+    pub fn flash(&mut self, variant: FlashVariant, rom: &[u8]) -> Result<Vec<u16>> {
+        let cart = FlashCart::new(variant);
+        let (manufacturer, device) = cart.identify(&mut self.pins);
+        // Progress goes to stderr, so stdout only carries what the caller prints
+        eprintln!("Flash manufacturer {:#04x}, device {:#04x}", manufacturer, device);
+        eprintln!("Erasing..");
+        cart.erase_chip(&mut self.pins)?;
+        cart.program(&mut self.pins, rom)?;
+        Ok(cart.verify(&mut self.pins, rom))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cartridge;
+    use crate::simulator::{test_rom, Mbc5Model, SimulatedBus};
+    use std::time::Duration;
+
+    const RETRIES: u32 = 2;
+
+    fn cartridge(rom: &[u8], ram_size: usize) -> Cartridge<SimulatedBus<Mbc5Model>> {
+        Cartridge::new(SimulatedBus::new(Mbc5Model::new(rom.to_vec(), ram_size)))
+    }
+
+    #[test]
+    fn shift_register_latches_address() {
+        let rom = test_rom(0x19, 0x01, 0x00);
+        let mut cart = cartridge(&rom, 0);
+        for address in [0x0000, 0x0147, 0x3FFF, 0x4000, 0x7FFF] {
+            assert_eq!(cart.read_byte(address), rom[address as usize]);
+        }
+    }
+
+    #[test]
+    fn reads_header() {
+        let rom = test_rom(0x1B, 0x03, 0x03);
+        let header = cartridge(&rom, 0).read_header();
+        assert_eq!(header.title, "SIMULATE");
+        assert_eq!(header.cartridge_type, 0x1B);
+        assert_eq!(header.rom_banks(), 16);
+        assert!(header.checksum_ok());
+    }
+
+    #[test]
+    fn simulated_dump_matches_rom() {
+        let rom = test_rom(0x19, 0x03, 0x00);
+        let (dump, report) = cartridge(&rom, 0).read_rom(RETRIES);
+        assert!(dump == rom, "Dump differs from the simulated cartridge");
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn retries_flaky_banks() {
+        let rom = test_rom(0x19, 0x02, 0x00);
+        // Reads of bank 0 and bank 1 each happen twice, the second read of bank 2 starts at 0x14000
+        let bus = SimulatedBus::new(Mbc5Model::new(rom.clone(), 0)).with_glitched_reads(vec![0x14000 + 0x123]);
+        let (dump, report) = Cartridge::new(bus).read_rom(RETRIES);
+        assert!(dump == rom, "Retried dump differs from the simulated cartridge");
+        assert_eq!(report.retried_banks, vec![2]);
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn reports_unstable_banks() {
+        let rom = test_rom(0x19, 0x02, 0x00);
+        // Every other read of bank 1 is corrupted, so no two reads in a row agree
+        let glitches = (0..=RETRIES as usize + 1).step_by(2).map(|read| 0x8000 + read * 0x4000).collect();
+        let bus = SimulatedBus::new(Mbc5Model::new(rom, 0)).with_glitched_reads(glitches);
+        let (_, report) = Cartridge::new(bus).read_rom(RETRIES);
+        assert_eq!(report.unstable_banks, vec![1]);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn reports_checksum_mismatch() {
+        let rom = test_rom(0x19, 0x01, 0x00);
+        let bus = SimulatedBus::new(Mbc5Model::new(rom, 0)).with_stuck_low(0x80);
+        let (_, report) = Cartridge::new(bus).read_rom(RETRIES);
+        assert!(report.unstable_banks.is_empty());
+        assert!(!report.header_checksum_ok || !report.global_checksum_ok);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn calibrates_bus_delay() {
+        let rom = test_rom(0x19, 0x01, 0x00);
+        let bus = SimulatedBus::new(Mbc5Model::new(rom.clone(), 0)).with_min_delay(Duration::from_nanos(1000));
+        let mut cart = Cartridge::new(bus);
+        let delay = cart.calibrate().unwrap();
+        // 1250 ns is the fastest reliable step, so one step slower is kept
+        assert_eq!(delay, Duration::from_nanos(2500));
+        assert_eq!(cart.pins().delay(), delay);
+        assert_eq!(cart.delay(), delay);
+        assert!(cart.read_rom(2).0 == rom);
+
+        let bus = SimulatedBus::new(Mbc5Model::new(rom, 0));
+        assert_eq!(Cartridge::new(bus).calibrate().unwrap(), Duration::ZERO);
+    }
+
+    #[test]
+    fn calibration_requires_cartridge() {
+        let mut rom = test_rom(0x19, 0x01, 0x00);
+        rom[0x14D] ^= 0xFF;
+        assert!(cartridge(&rom, 0).calibrate().is_err());
+    }
+
+    #[test]
+    fn save_ram_round_trip() {
+        let rom = test_rom(0x1B, 0x01, 0x03);
+        let mut cart = cartridge(&rom, 0x8000);
+        let save: Vec<u8> = (0..0x8000).map(|i| (i * 7 / 3) as u8).collect();
+        cart.write_ram(&save).unwrap();
+        assert!(cart.pins().cartridge().ram == save);
+        assert!(cart.read_ram().unwrap() == save);
+        assert!(cart.write_ram(&save[..0x2000]).is_err());
+    }
+
+    #[test]
+    fn rom_only_has_no_save_ram() {
+        let rom = test_rom(0x00, 0x00, 0x00);
+        assert!(cartridge(&rom, 0).read_ram().is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::bus::write_byte;
+use crate::pins::CartridgePins;
+
+/// The bank switching scheme of a cartridge, selected from header byte 0x147
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mapper {
+    RomOnly,
+    MBC1,
+    MBC2,
+    MBC3,
+    MBC5,
+}
+
+impl Mapper {
+    pub fn from_cartridge_type(cartridge_type: u8) -> Mapper {
+        match cartridge_type {
+            0x00 | 0x08 | 0x09 => Mapper::RomOnly,
+            0x01 ..= 0x03 => Mapper::MBC1,
+            0x05 ..= 0x06 => Mapper::MBC2,
+            0x0F ..= 0x13 => Mapper::MBC3,
+            // MBC5 and most flash cartridges, also the best guess for anything unknown
+            _ => Mapper::MBC5,
+        }
+    }
+
+    /// Maps `bank` into the address space and returns the address where it starts
+    pub fn select_rom_bank<P: CartridgePins + ?Sized>(self, pins: &mut P, bank: u16) -> u16 {
+        match self {
+            Mapper::RomOnly => 0x4000,
+            Mapper::MBC1 => {
+                write_byte(pins, 0x2000, (bank & 0x1F) as u8);
+                write_byte(pins, 0x4000, ((bank >> 5) & 0x03) as u8);
+                if bank & 0x1F == 0 {
+                    // Banks 0x20, 0x40 and 0x60 can not be mapped at 0x4000, as a 0 in the lower
+                    // bits selects bank 1 instead. In mode 1 they show up at 0x0000.
+                    write_byte(pins, 0x6000, 1);
+                    0x0000
+                } else {
+                    write_byte(pins, 0x6000, 0);
+                    0x4000
+                }
+            },
+            Mapper::MBC2 => {
+                // The ROM bank register is only selected with address bit 8 set
+                write_byte(pins, 0x2100, (bank & 0x0F) as u8);
+                0x4000
+            },
+            Mapper::MBC3 => {
+                // Regular MBC3 ignores bit 7, MBC30 uses it for its 256 banks
+                write_byte(pins, 0x2000, bank as u8);
+                0x4000
+            },
+            Mapper::MBC5 => {
+                write_byte(pins, 0x2000, bank as u8);
+                write_byte(pins, 0x3000, ((bank >> 8) & 1) as u8);
+                0x4000
+            },
+        }
+    }
+
+    /// Maps RAM bank `bank` at 0xA000
+    pub fn select_ram_bank<P: CartridgePins + ?Sized>(self, pins: &mut P, bank: usize) {
+        match self {
+            Mapper::MBC1 => {
+                // RAM banking only works in the advanced banking mode
+                write_byte(pins, 0x6000, 1);
+                write_byte(pins, 0x4000, (bank & 0x03) as u8);
+            },
+            Mapper::MBC3 => write_byte(pins, 0x4000, (bank & 0x07) as u8),
+            _ => write_byte(pins, 0x4000, (bank & 0x0F) as u8),
+        }
+    }
+
+    /// Puts the mapper back into its power-on banking state
+    pub fn reset_banking<P: CartridgePins + ?Sized>(self, pins: &mut P) {
+        match self {
+            Mapper::RomOnly => {},
+            Mapper::MBC1 => {
+                write_byte(pins, 0x6000, 0);
+                write_byte(pins, 0x4000, 0);
+                write_byte(pins, 0x2000, 1);
+            },
+            _ => { self.select_rom_bank(pins, 1); },
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::config::BusConfig;
+use std::hint::spin_loop;
+use std::time::{Duration, Instant};
+use rppal::gpio::{Gpio, IoPin, Level, Mode, OutputPin};
+
+/// Busy-waits for `duration`, as sleeping the thread takes far longer than a bus delay
+fn sleep(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        spin_loop();
+    }
+}
+
+/// The pin operations used to drive the cartridge bus.
+///
+/// The address is shifted into a pair of 74HC595 shift registers through the serial data, clock and
+/// latch lines, while the eight data lines are connected to the cartridge directly.
+pub trait CartridgePins {
+    fn set_cs(&mut self, level: Level);
+    fn set_read(&mut self, level: Level);
+    fn set_write(&mut self, level: Level);
+    fn set_latch(&mut self, level: Level);
+    fn set_clock(&mut self, level: Level);
+    fn set_serial_data(&mut self, level: Level);
+    fn set_data_mode(&mut self, mode: Mode);
+    fn write_data(&mut self, value: u8);
+    fn read_data(&mut self) -> u8;
+
+    /// Waits long enough for the bus to settle after changing a line
+    fn delay(&mut self) {}
+
+    /// Changes how long `delay` waits
+    fn set_delay(&mut self, _delay: Duration) {}
+}
+
+impl<P: CartridgePins + ?Sized> CartridgePins for Box<P> {
+    fn set_cs(&mut self, level: Level) { (**self).set_cs(level) }
+    fn set_read(&mut self, level: Level) { (**self).set_read(level) }
+    fn set_write(&mut self, level: Level) { (**self).set_write(level) }
+    fn set_latch(&mut self, level: Level) { (**self).set_latch(level) }
+    fn set_clock(&mut self, level: Level) { (**self).set_clock(level) }
+    fn set_serial_data(&mut self, level: Level) { (**self).set_serial_data(level) }
+    fn set_data_mode(&mut self, mode: Mode) { (**self).set_data_mode(mode) }
+    fn write_data(&mut self, value: u8) { (**self).write_data(value) }
+    fn read_data(&mut self) -> u8 { (**self).read_data() }
+    fn delay(&mut self) { (**self).delay() }
+    fn set_delay(&mut self, delay: Duration) { (**self).set_delay(delay) }
+}
+
+/// The adapter board on the Raspberry Pi GPIO header
+pub struct GpioPins {
+    _gpio: Gpio,
+    cs_pin: OutputPin,
+    latch_pin: OutputPin,
+    clock_pin: OutputPin,
+    data_pin: OutputPin,
+    read_pin: OutputPin,
+    write_pin: OutputPin,
+    data_pins: Vec<IoPin>,
+    data_pins_mode: Mode,
+    delay: Duration,
+}
+
+impl GpioPins {
+    /// Opens the pins of the original board
+    pub fn new() -> Result<GpioPins, rppal::gpio::Error> {
+        GpioPins::with_config(&BusConfig::default())
+    }
+
+    /// Opens the pins given in `config`. A calibrated delay starts out at the default.
+    pub fn with_config(config: &BusConfig) -> Result<GpioPins, rppal::gpio::Error> {
+        let gpio = Gpio::new()?;
+        let cs_pin = gpio.get(config.cs)?.into_output();
+        let latch_pin = gpio.get(config.latch)?.into_output();
+        let clock_pin = gpio.get(config.clock)?.into_output();
+        let data_pin = gpio.get(config.serial_data)?.into_output();
+        let read_pin = gpio.get(config.read)?.into_output();
+        let write_pin = gpio.get(config.write)?.into_output();
+        let data_pins_mode = Mode::Input;
+        let data_pins = config.data.iter()
+            .map(|pin| gpio.get(*pin).map(|p| p.into_io(data_pins_mode)))
+            .collect::<Result<Vec<IoPin>, _>>()?;
+        Ok(GpioPins {
+            _gpio: gpio,
+            cs_pin,
+            latch_pin,
+            clock_pin,
+            data_pin,
+            read_pin,
+            write_pin,
+            data_pins,
+            data_pins_mode,
+            delay: config.delay(),
+        })
+    }
+
+    fn change_data_pins_mode(&mut self, mode: Mode) {
+        if self.data_pins_mode == mode {
+            return;
+        }
+        if mode == Mode::Input {
+            for pin in self.data_pins.iter_mut() {
+                pin.set_low();
+            }
+        }
+        for pin in self.data_pins.iter_mut() {
+            pin.set_mode(mode);
+        }
+        self.data_pins_mode = mode;
+    }
+}
+
+impl CartridgePins for GpioPins {
+    fn set_cs(&mut self, level: Level) {
+        self.cs_pin.write(level);
+    }
+
+    fn set_read(&mut self, level: Level) {
+        self.read_pin.write(level);
+    }
+
+    fn set_write(&mut self, level: Level) {
+        self.write_pin.write(level);
+    }
+
+    fn set_latch(&mut self, level: Level) {
+        self.latch_pin.write(level);
+    }
+
+    fn set_clock(&mut self, level: Level) {
+        self.clock_pin.write(level);
+    }
+
+    fn set_serial_data(&mut self, level: Level) {
+        self.data_pin.write(level);
+    }
+
+    fn set_data_mode(&mut self, mode: Mode) {
+        self.change_data_pins_mode(mode);
+    }
+
+    fn write_data(&mut self, value: u8) {
+        for (index, pin) in self.data_pins.iter_mut().enumerate() {
+            if value & (1 << index) != 0 {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        let mut value = 0u8;
+        for (bit, pin) in self.data_pins.iter().enumerate() {
+            if pin.read() == Level::High {
+                value |= (1 << bit) as u8;
+            }
+        }
+        value
+    }
+
+    fn delay(&mut self) {
+        sleep(self.delay);
+    }
+
+    fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+}
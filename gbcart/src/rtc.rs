@@ -0,0 +1,117 @@
+//! The RTC footer which VBA-M, BGB and SameBoy append to the save RAM of MBC3 cartridges.
+
+use std::convert::TryInto;
+
+/// Size of the footer with a 64 bit timestamp
+pub const RTC_FOOTER_LEN: usize = 48;
+/// Size of the older footer with a 32 bit timestamp, which some emulators still write
+const RTC_FOOTER_LEN_32: usize = 44;
+/// Day counter high register: bit 0 of the day, halt, and day counter carry
+const DH_DAY: u8 = 0x01;
+const DH_HALT: u8 = 0x40;
+const DH_CARRY: u8 = 0x80;
+
+/// The clock registers S, M, H, DL and DH, followed by their latched copy and the unix time the
+/// save was written at. Every register is stored as a little-endian 32 bit value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RtcFooter {
+    pub registers: [u8; 5],
+    pub latched: [u8; 5],
+    pub timestamp: u64,
+}
+
+impl RtcFooter {
+    pub fn from_bytes(data: &[u8]) -> Option<RtcFooter> {
+        if data.len() != RTC_FOOTER_LEN && data.len() != RTC_FOOTER_LEN_32 {
+            return None;
+        }
+        let word = |i: usize| data[i * 4];
+        let mut footer = RtcFooter::default();
+        for i in 0..5 {
+            footer.registers[i] = word(i);
+            footer.latched[i] = word(5 + i);
+        }
+        footer.timestamp = match data.len() {
+            RTC_FOOTER_LEN => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            _ => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+        };
+        Some(footer)
+    }
+
+    pub fn to_bytes(&self) -> [u8; RTC_FOOTER_LEN] {
+        let mut data = [0; RTC_FOOTER_LEN];
+        for (i, value) in self.registers.iter().chain(self.latched.iter()).enumerate() {
+            data[i * 4] = *value;
+        }
+        data[40..].copy_from_slice(&self.timestamp.to_le_bytes());
+        data
+    }
+
+    /// Splits a save into its `ram_len` bytes of RAM and the footer behind them
+    pub fn split(save: &[u8], ram_len: usize) -> Option<(&[u8], RtcFooter)> {
+        if save.len() <= ram_len {
+            return None;
+        }
+        let (ram, footer) = save.split_at(ram_len);
+        RtcFooter::from_bytes(footer).map(|footer| (ram, footer))
+    }
+
+    /// The registers at unix time `now`, after the clock kept running since the save was written
+    pub fn registers_at(&self, now: u64) -> [u8; 5] {
+        advance_rtc(self.registers, now.saturating_sub(self.timestamp))
+    }
+}
+
+/// Seconds counted by the clock registers, up to 512 days
+pub fn rtc_seconds(registers: &[u8; 5]) -> u64 {
+    let days = (((registers[4] & DH_DAY) as u64) << 8) | registers[3] as u64;
+    (registers[0] & 0x3F) as u64
+        + (registers[1] & 0x3F) as u64 * 60
+        + (registers[2] & 0x1F) as u64 * 3600
+        + days * 3600 * 24
+}
+
+/// Runs the clock registers for `seconds`, unless they are halted. Overflowing the 9 bit day
+/// counter sets the carry bit, which stays set until the game clears it.
+pub fn advance_rtc(registers: [u8; 5], seconds: u64) -> [u8; 5] {
+    if registers[4] & DH_HALT != 0 {
+        return registers;
+    }
+    let total = rtc_seconds(&registers) + seconds;
+    let days = total / (3600 * 24);
+    let mut dh = (registers[4] & (DH_HALT | DH_CARRY)) | ((days >> 8) as u8 & DH_DAY);
+    if days >= 512 {
+        dh |= DH_CARRY;
+    }
+    [(total % 60) as u8, ((total / 60) % 60) as u8, ((total / 3600) % 24) as u8, days as u8, dh]
+}
+
+#[cfg(test)]
+mod test {
+    use super::{advance_rtc, RtcFooter, RTC_FOOTER_LEN};
+
+    #[test]
+    fn footer_round_trip() {
+        let footer = RtcFooter { registers: [1, 2, 3, 4, 0x41], latched: [5, 6, 7, 8, 0x01], timestamp: 0x1_2345_6789 };
+        let bytes = footer.to_bytes();
+        assert_eq!(bytes.len(), RTC_FOOTER_LEN);
+        assert_eq!(&bytes[16..20], &[0x41, 0, 0, 0]);
+        assert_eq!(RtcFooter::from_bytes(&bytes), Some(footer));
+
+        // The 32 bit variant drops the upper half of the timestamp
+        let short = RtcFooter::from_bytes(&bytes[..44]).unwrap();
+        assert_eq!(short.timestamp, 0x2345_6789);
+
+        let mut save = vec![0xAA; 0x2000];
+        save.extend_from_slice(&bytes);
+        assert_eq!(RtcFooter::split(&save, 0x2000), Some((&save[..0x2000], footer)));
+        assert_eq!(RtcFooter::split(&save[..0x2000 + 8], 0x2000), None);
+    }
+
+    #[test]
+    fn advances_with_carry_and_halt() {
+        assert_eq!(advance_rtc([59, 59, 23, 0xFF, 0x00], 1), [0, 0, 0, 0x00, 0x01]);
+        assert_eq!(advance_rtc([59, 59, 23, 0xFF, 0x01], 1), [0, 0, 0, 0x00, 0x80]);
+        assert_eq!(advance_rtc([1, 2, 3, 4, 0x40], 1000), [1, 2, 3, 4, 0x40]);
+    }
+}
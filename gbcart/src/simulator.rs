@@ -0,0 +1,400 @@
+//! A software model of the cartridge adapter, used to test the GPIO bus protocol without hardware.
+
+use crate::flash::FlashVariant;
+use crate::pins::CartridgePins;
+use rppal::gpio::{Level, Mode};
+use std::time::Duration;
+
+/// The cartridge side of the simulated bus
+pub trait CartridgeModel {
+    fn read_rom(&mut self, address: u16) -> u8;
+    fn read_ram(&mut self, address: u16) -> u8;
+    fn write_rom(&mut self, address: u16, value: u8);
+    fn write_ram(&mut self, address: u16, value: u8);
+}
+
+/// A plain MBC5 cartridge, with up to 512 ROM banks and 16 RAM banks
+pub struct Mbc5Model {
+    pub rom: Vec<u8>,
+    pub ram: Vec<u8>,
+    rom_bank: usize,
+    ram_bank: usize,
+    ram_enabled: bool,
+}
+
+impl Mbc5Model {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> Mbc5Model {
+        Mbc5Model { rom, ram: vec![0; ram_size], rom_bank: 1, ram_bank: 0, ram_enabled: false }
+    }
+}
+
+impl CartridgeModel for Mbc5Model {
+    fn read_rom(&mut self, address: u16) -> u8 {
+        let offset = match address {
+            0x0000 ..= 0x3FFF => address as usize,
+            _ => self.rom_bank * 0x4000 + (address as usize & 0x3FFF),
+        };
+        self.rom.get(offset % self.rom.len()).copied().unwrap_or(0xFF)
+    }
+
+    fn read_ram(&mut self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        let offset = (self.ram_bank * 0x2000 + (address as usize & 0x1FFF)) % self.ram.len();
+        self.ram[offset]
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            0x0000 ..= 0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000 ..= 0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as usize,
+            0x3000 ..= 0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value as usize & 1) << 8),
+            0x4000 ..= 0x5FFF => self.ram_bank = value as usize & 0x0F,
+            _ => {},
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let offset = (self.ram_bank * 0x2000 + (address as usize & 0x1FFF)) % self.ram.len();
+        self.ram[offset] = value;
+    }
+}
+
+/// Manufacturer id the simulated flash chip reports
+pub const FLASH_MANUFACTURER: u8 = 0x01;
+/// Device id the simulated flash chip reports
+pub const FLASH_DEVICE: u8 = 0xAD;
+// Status reads before an embedded operation finishes
+const FLASH_BUSY_READS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FlashState {
+    Read,
+    /// Counts the bus cycles of the unlock sequence seen so far
+    Unlock(u8),
+    Autoselect,
+    Program,
+    /// An erase was requested, and the chip waits for a second unlock sequence
+    EraseArmed(u8),
+}
+
+/// An embedded operation in progress
+struct FlashOperation {
+    /// What the polled address reads once the operation is done
+    result: u8,
+    reads_left: usize,
+}
+
+/// A reproduction cartridge: an AMD style flash chip behind an MBC5 style mapper, wired as `variant`
+pub struct FlashModel {
+    pub flash: Vec<u8>,
+    variant: FlashVariant,
+    rom_bank: usize,
+    state: FlashState,
+    operation: Option<FlashOperation>,
+    timeout: bool,
+}
+
+impl FlashModel {
+    /// A chip of `size` bytes filled with `0x00`, as left by an earlier game
+    pub fn new(variant: FlashVariant, size: usize) -> FlashModel {
+        FlashModel { flash: vec![0; size], variant, rom_bank: 1, state: FlashState::Read, operation: None, timeout: false }
+    }
+
+    /// Makes every embedded operation exceed the internal time limit of the chip
+    pub fn with_timeout(mut self) -> FlashModel {
+        self.timeout = true;
+        self
+    }
+
+    /// The data lines as the chip sees them
+    fn wire(&self, value: u8) -> u8 {
+        match self.variant {
+            FlashVariant::LV29Swapped => (value & 0xFC) | ((value & 0x01) << 1) | ((value & 0x02) >> 1),
+            _ => value,
+        }
+    }
+
+    fn chip_address(&self, address: u16) -> usize {
+        let offset = match address {
+            0x0000 ..= 0x3FFF => address as usize,
+            _ => self.rom_bank * 0x4000 + (address as usize & 0x3FFF),
+        };
+        offset % self.flash.len()
+    }
+
+    fn start(&mut self, result: u8) {
+        self.operation = Some(FlashOperation { result, reads_left: FLASH_BUSY_READS });
+        self.state = FlashState::Read;
+    }
+
+    fn write_chip(&mut self, address: u16, value: u8) {
+        let (first, second) = match self.variant {
+            FlashVariant::AM29F016 => (0x555, 0x2AA),
+            FlashVariant::LV29 | FlashVariant::LV29Swapped => (0xAAA, 0x555),
+        };
+        if self.state == FlashState::Program {
+            // Programming can only clear bits
+            let offset = self.chip_address(address);
+            self.flash[offset] &= value;
+            return self.start(value);
+        }
+        if value == 0xF0 {
+            self.state = FlashState::Read;
+            self.operation = None;
+            return;
+        }
+        self.state = match (self.state, address, value) {
+            (FlashState::Read | FlashState::Autoselect, a, 0xAA) if a == first => FlashState::Unlock(1),
+            (FlashState::Unlock(1), a, 0x55) if a == second => FlashState::Unlock(2),
+            (FlashState::Unlock(2), a, 0x90) if a == first => FlashState::Autoselect,
+            (FlashState::Unlock(2), a, 0xA0) if a == first => FlashState::Program,
+            (FlashState::Unlock(2), a, 0x80) if a == first => FlashState::EraseArmed(0),
+            (FlashState::EraseArmed(0), a, 0xAA) if a == first => FlashState::EraseArmed(1),
+            (FlashState::EraseArmed(1), a, 0x55) if a == second => FlashState::EraseArmed(2),
+            (FlashState::EraseArmed(2), a, 0x10) if a == first => {
+                self.flash.fill(0xFF);
+                return self.start(0xFF);
+            },
+            (FlashState::Autoselect, _, _) => FlashState::Autoselect,
+            _ => FlashState::Read,
+        };
+    }
+}
+
+impl CartridgeModel for FlashModel {
+    fn read_rom(&mut self, address: u16) -> u8 {
+        let value = if let Some(operation) = self.operation.as_mut() {
+            // Data polling: DQ7 reads inverted and DQ6 toggles until the operation is done
+            let status = (!operation.result & 0x80) | ((operation.reads_left as u8 & 1) << 6);
+            if self.timeout {
+                status | 0x20
+            } else {
+                operation.reads_left -= 1;
+                if operation.reads_left == 0 {
+                    self.operation = None;
+                }
+                status
+            }
+        } else if self.state == FlashState::Autoselect {
+            let device_address = if self.variant == FlashVariant::AM29F016 { 0x0001 } else { 0x0002 };
+            match address {
+                0x0000 => FLASH_MANUFACTURER,
+                a if a == device_address => FLASH_DEVICE,
+                _ => 0x00,
+            }
+        } else {
+            self.flash[self.chip_address(address)]
+        };
+        // The swap is its own inverse
+        self.wire(value)
+    }
+
+    fn read_ram(&mut self, _address: u16) -> u8 {
+        0xFF
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        // The chip sees every write, the mapper only those to its registers
+        let chip_value = self.wire(value);
+        self.write_chip(address, chip_value);
+        match address {
+            0x2000 ..= 0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | value as usize,
+            0x3000 ..= 0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((value as usize & 1) << 8),
+            _ => {},
+        }
+    }
+
+    fn write_ram(&mut self, _address: u16, _value: u8) {}
+}
+
+/// Two daisy-chained 74HC595 shift registers which drive the 16 address lines
+#[derive(Default)]
+pub struct ShiftRegister {
+    shift: u16,
+    output: u16,
+    clock: Option<Level>,
+    latch: Option<Level>,
+}
+
+impl ShiftRegister {
+    /// Shifts `data` in on a rising edge of the clock
+    pub fn set_clock(&mut self, level: Level, data: Level) {
+        if self.clock == Some(Level::Low) && level == Level::High {
+            self.shift = (self.shift << 1) | (data == Level::High) as u16;
+        }
+        self.clock = Some(level);
+    }
+
+    /// Copies the shifted bits to the outputs on a rising edge of the latch
+    pub fn set_latch(&mut self, level: Level) {
+        if self.latch == Some(Level::Low) && level == Level::High {
+            self.output = self.shift;
+        }
+        self.latch = Some(level);
+    }
+
+    pub fn output(&self) -> u16 {
+        self.output
+    }
+}
+
+/// The adapter board with an emulated cartridge plugged in
+pub struct SimulatedBus<M: CartridgeModel> {
+    address: ShiftRegister,
+    cartridge: M,
+    serial_data: Level,
+    cs: Level,
+    read: Level,
+    write: Level,
+    data_mode: Mode,
+    data_out: u8,
+    reads: usize,
+    glitched_reads: Vec<usize>,
+    stuck_low: u8,
+    delay: Duration,
+    min_delay: Duration,
+}
+
+impl<M: CartridgeModel> SimulatedBus<M> {
+    pub fn new(cartridge: M) -> SimulatedBus<M> {
+        SimulatedBus {
+            address: ShiftRegister::default(),
+            cartridge,
+            serial_data: Level::Low,
+            cs: Level::High,
+            read: Level::High,
+            write: Level::High,
+            data_mode: Mode::Input,
+            data_out: 0,
+            reads: 0,
+            glitched_reads: Vec::new(),
+            stuck_low: 0,
+            delay: Duration::ZERO,
+            min_delay: Duration::ZERO,
+        }
+    }
+
+    /// Flips the lowest data bit of the given cartridge reads, counting from 0, like a dirty contact
+    pub fn with_glitched_reads(mut self, reads: Vec<usize>) -> SimulatedBus<M> {
+        self.glitched_reads = reads;
+        self
+    }
+
+    /// Forces the data lines in `mask` low on every read, like a broken trace
+    pub fn with_stuck_low(mut self, mask: u8) -> SimulatedBus<M> {
+        self.stuck_low = mask;
+        self
+    }
+
+    /// Makes every other read unreliable while the bus delay is shorter than `delay`
+    pub fn with_min_delay(mut self, delay: Duration) -> SimulatedBus<M> {
+        self.min_delay = delay;
+        self
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    pub fn cartridge(&self) -> &M {
+        &self.cartridge
+    }
+
+    fn check_contention(&self) {
+        assert!(!(self.data_mode == Mode::Output && self.read == Level::Low),
+                "Bus contention: the data lines are driven while the cartridge outputs {:04X}", self.address.output());
+    }
+}
+
+impl<M: CartridgeModel> CartridgePins for SimulatedBus<M> {
+    fn set_cs(&mut self, level: Level) {
+        self.cs = level;
+    }
+
+    fn set_read(&mut self, level: Level) {
+        self.read = level;
+        self.check_contention();
+    }
+
+    fn set_write(&mut self, level: Level) {
+        // The cartridge latches the data on the rising edge of /WR
+        if self.write == Level::Low && level == Level::High && self.data_mode == Mode::Output {
+            let address = self.address.output();
+            match address {
+                0x0000 ..= 0x7FFF => self.cartridge.write_rom(address, self.data_out),
+                0xA000 ..= 0xBFFF if self.cs == Level::Low => self.cartridge.write_ram(address, self.data_out),
+                _ => {},
+            }
+        }
+        self.write = level;
+    }
+
+    fn set_latch(&mut self, level: Level) {
+        self.address.set_latch(level);
+    }
+
+    fn set_clock(&mut self, level: Level) {
+        self.address.set_clock(level, self.serial_data);
+    }
+
+    fn set_serial_data(&mut self, level: Level) {
+        self.serial_data = level;
+    }
+
+    fn set_data_mode(&mut self, mode: Mode) {
+        self.data_mode = mode;
+        self.check_contention();
+    }
+
+    fn write_data(&mut self, value: u8) {
+        self.data_out = value;
+    }
+
+    fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    fn read_data(&mut self) -> u8 {
+        if self.data_mode == Mode::Output {
+            return self.data_out;
+        }
+        if self.read == Level::High {
+            // Nothing drives the bus, the pull-ups win
+            return 0xFF;
+        }
+        let address = self.address.output();
+        let value = match address {
+            0x0000 ..= 0x7FFF => self.cartridge.read_rom(address),
+            0xA000 ..= 0xBFFF if self.cs == Level::Low => self.cartridge.read_ram(address),
+            _ => 0xFF,
+        };
+        let too_fast = self.delay < self.min_delay && self.reads % 2 == 1;
+        let glitch = (self.glitched_reads.contains(&self.reads) || too_fast) as u8;
+        self.reads += 1;
+        (value ^ glitch) & !self.stuck_low
+    }
+}
+
+/// Builds a ROM with a valid header and global checksum, titled "SIMULATE"
+pub fn test_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    let banks = 2usize << rom_size;
+    let mut data: Vec<u8> = (0..banks * 0x4000)
+        .map(|i| ((i / 0x4000) as u8).wrapping_mul(31) ^ (i as u8))
+        .collect();
+    data[0x134..0x150].fill(0);
+    data[0x134..0x13C].copy_from_slice(b"SIMULATE");
+    data[0x147] = cartridge_type;
+    data[0x148] = rom_size;
+    data[0x149] = ram_size;
+    data[0x14D] = crate::header::header_checksum(&data);
+    // 0x14E and 0x14F are still 0, so they do not count towards the sum
+    let global = data.iter().fold(0u16, |sum, &v| sum.wrapping_add(v as u16));
+    data[0x14E] = (global >> 8) as u8;
+    data[0x14F] = global as u8;
+    data
+}
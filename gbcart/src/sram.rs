@@ -0,0 +1,181 @@
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::bus::{read_byte, write_byte};
+use crate::error::{Error, Result};
+use crate::header::CartridgeHeader;
+use crate::mapper::Mapper;
+use crate::pins::CartridgePins;
+use crate::rtc::{RtcFooter, RTC_FOOTER_LEN};
+
+const RAM_START: u16 = 0xA000;
+const RAM_BANK_SIZE: usize = 0x2000;
+const MBC2_RAM_SIZE: usize = 512;
+// Older rboy saves keep the RTC of MBC3 cartridges as an 8 byte big-endian prefix in front of the RAM
+const MBC3_RTC_PREFIX: usize = 8;
+
+/// Describes the save RAM of a cartridge, as read from its header
+pub struct SaveRam {
+    cartridge_type: u8,
+    mapper: Mapper,
+    banks: usize,
+}
+
+impl SaveRam {
+    pub fn from_header(header: &CartridgeHeader) -> Result<SaveRam> {
+        let cartridge_type = header.cartridge_type;
+        let has_ram = matches!(cartridge_type, 0x02 | 0x03 | 0x05 | 0x06 | 0x10 | 0x12 | 0x13 | 0x1A | 0x1B | 0x1D | 0x1E);
+        // MBC3+TIMER+BATTERY keeps only the clock, which is saved as the footer behind no RAM
+        let clock_only = cartridge_type == 0x0F;
+        if !has_ram && !clock_only {
+            return Err(Error::NoSaveRam(cartridge_type));
+        }
+        let banks = match header.ram_size {
+            _ if clock_only => 0,
+            1 | 2 => 1,
+            3 => 4,
+            4 => 16,
+            5 => 8,
+            _ => 0,
+        };
+        let save = SaveRam { cartridge_type, mapper: header.mapper(), banks };
+        if save.ram_len() == 0 && !clock_only {
+            return Err(Error::UnknownRamSize(header.ram_size));
+        }
+        Ok(save)
+    }
+
+    fn is_mbc2(&self) -> bool {
+        self.mapper == Mapper::MBC2
+    }
+
+    fn is_mbc3(&self) -> bool {
+        self.mapper == Mapper::MBC3
+    }
+
+    fn has_rtc(&self) -> bool {
+        self.cartridge_type == 0x0F || self.cartridge_type == 0x10
+    }
+
+    /// Size of the RAM in bytes, without any RTC data
+    pub fn ram_len(&self) -> usize {
+        if self.is_mbc2() {
+            MBC2_RAM_SIZE
+        } else {
+            self.banks * RAM_BANK_SIZE
+        }
+    }
+
+    /// Reads the whole RAM, followed by the RTC footer if the cartridge has a clock
+    pub fn dump<P: CartridgePins + ?Sized>(&self, pins: &mut P) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.ram_len() + RTC_FOOTER_LEN);
+        enable_ram(pins);
+        if self.is_mbc2() {
+            for offset in 0..MBC2_RAM_SIZE {
+                // Only the lower nibble is connected
+                data.push(read_byte(pins, RAM_START + offset as u16) | 0xF0);
+            }
+        } else {
+            for bank in 0..self.banks {
+                self.mapper.select_ram_bank(pins, bank);
+                for offset in 0..RAM_BANK_SIZE {
+                    data.push(read_byte(pins, RAM_START + offset as u16));
+                }
+            }
+        }
+        if self.mapper == Mapper::MBC1 {
+            write_byte(pins, 0x6000, 0);
+        }
+        if self.has_rtc() {
+            let registers = read_rtc(pins);
+            let footer = RtcFooter { registers, latched: registers, timestamp: now() };
+            data.extend_from_slice(&footer.to_bytes());
+        }
+        disable_ram(pins);
+        data
+    }
+
+    /// Writes a `.gbsave` or raw `.sav` file back to the cartridge. The clock is set from an RTC
+    /// footer, or from the prefix of older rboy saves.
+    pub fn restore<P: CartridgePins + ?Sized>(&self, pins: &mut P, save: &[u8]) -> Result<()> {
+        let (ram, rtc) = if let Some((ram, footer)) = RtcFooter::split(save, self.ram_len()).filter(|_| self.is_mbc3()) {
+            (ram, Some(footer.registers_at(now())))
+        } else if self.is_mbc3() && save.len() == MBC3_RTC_PREFIX + self.ram_len() {
+            let rtc_zero = u64::from_be_bytes(save[..MBC3_RTC_PREFIX].try_into().unwrap());
+            (&save[MBC3_RTC_PREFIX..], Some(registers_since(rtc_zero)))
+        } else {
+            (save, None)
+        };
+        if ram.len() != self.ram_len() {
+            return Err(Error::SaveSizeMismatch { expected: self.ram_len(), actual: save.len() });
+        }
+
+        enable_ram(pins);
+        if self.is_mbc2() {
+            for (offset, value) in ram.iter().enumerate() {
+                write_byte(pins, RAM_START + offset as u16, value & 0x0F);
+            }
+        } else {
+            for (bank, chunk) in ram.chunks(RAM_BANK_SIZE).enumerate() {
+                self.mapper.select_ram_bank(pins, bank);
+                for (offset, value) in chunk.iter().enumerate() {
+                    write_byte(pins, RAM_START + offset as u16, *value);
+                }
+            }
+        }
+        if self.mapper == Mapper::MBC1 {
+            write_byte(pins, 0x6000, 0);
+        }
+        if let Some(registers) = rtc.filter(|_| self.has_rtc()) {
+            write_rtc(pins, registers);
+        }
+        disable_ram(pins);
+        Ok(())
+    }
+}
+
+fn enable_ram<P: CartridgePins + ?Sized>(pins: &mut P) {
+    write_byte(pins, 0x0000, 0x0A);
+}
+
+fn disable_ram<P: CartridgePins + ?Sized>(pins: &mut P) {
+    write_byte(pins, 0x0000, 0);
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Latches the RTC and reads its registers
+fn read_rtc<P: CartridgePins + ?Sized>(pins: &mut P) -> [u8; 5] {
+    write_byte(pins, 0x6000, 0);
+    write_byte(pins, 0x6000, 1);
+    let mut registers = [0u8; 5];
+    for (index, register) in registers.iter_mut().enumerate() {
+        write_byte(pins, 0x4000, 0x08 + index as u8);
+        *register = read_byte(pins, RAM_START);
+    }
+    registers
+}
+
+/// The registers of a clock which was at zero at unix time `rtc_zero`
+fn registers_since(rtc_zero: u64) -> [u8; 5] {
+    let elapsed = now().saturating_sub(rtc_zero);
+    let days = (elapsed / (3600 * 24)) % 512;
+    [
+        (elapsed % 60) as u8,
+        ((elapsed / 60) % 60) as u8,
+        ((elapsed / 3600) % 24) as u8,
+        days as u8,
+        (days >> 8) as u8,
+    ]
+}
+
+fn write_rtc<P: CartridgePins + ?Sized>(pins: &mut P, registers: [u8; 5]) {
+    // Halt the clock while it is being set
+    write_byte(pins, 0x4000, 0x0C);
+    write_byte(pins, RAM_START, 0x40);
+    for (index, register) in registers.iter().enumerate() {
+        write_byte(pins, 0x4000, 0x08 + index as u8);
+        write_byte(pins, RAM_START, *register);
+    }
+}
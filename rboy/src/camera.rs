@@ -0,0 +1,193 @@
+//! Images for the sensor of the Game Boy Camera.
+
+use crate::{Error, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Width of the part of the sensor which the camera reads
+pub const SENSOR_W: usize = 128;
+/// Height of the part of the sensor which the camera reads
+pub const SENSOR_H: usize = 112;
+
+/// Provides the light falling on the camera sensor
+pub trait ImageSource: Send {
+    /// Returns a `SENSOR_W` x `SENSOR_H` grayscale frame, row by row, where 0 is black and 255 is white
+    fn capture(&mut self) -> Vec<u8>;
+}
+
+/// Opens an image source from a command line argument: `test` for the test pattern, a directory
+/// of PNG files for an image sequence, or a single PNG file
+pub fn open_source(spec: &str) -> Result<Box<dyn ImageSource>> {
+    let path = Path::new(spec);
+    if spec == "test" {
+        Ok(Box::new(TestPattern))
+    } else if path.is_dir() {
+        ImageSequence::from_dir(path).map(|v| Box::new(v) as Box<dyn ImageSource>)
+    } else {
+        StillImage::from_png(path).map(|v| Box::new(v) as Box<dyn ImageSource>)
+    }
+}
+
+/// Horizontal gradients which run in opposite directions in the top and bottom half, with a frame
+/// around the edge, so the whole tone range and the edge enhancement can be checked
+pub struct TestPattern;
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(SENSOR_W * SENSOR_H);
+        for y in 0..SENSOR_H {
+            for x in 0..SENSOR_W {
+                let value = if x < 4 || y < 4 || x >= SENSOR_W - 4 || y >= SENSOR_H - 4 {
+                    0
+                } else if y < SENSOR_H / 2 {
+                    x * 255 / (SENSOR_W - 1)
+                } else {
+                    255 - x * 255 / (SENSOR_W - 1)
+                };
+                frame.push(value as u8);
+            }
+        }
+        frame
+    }
+}
+
+/// The same image for every capture
+pub struct StillImage {
+    frame: Vec<u8>,
+}
+
+impl StillImage {
+    pub fn new(frame: Vec<u8>) -> Result<StillImage> {
+        if frame.len() != SENSOR_W * SENSOR_H {
+            return Err(Error::Camera("Camera image has incorrect size"));
+        }
+        Ok(StillImage { frame })
+    }
+
+    pub fn from_png<P: AsRef<Path>>(path: P) -> Result<StillImage> {
+        StillImage::new(load_png(path.as_ref())?)
+    }
+}
+
+impl ImageSource for StillImage {
+    fn capture(&mut self) -> Vec<u8> {
+        self.frame.clone()
+    }
+}
+
+/// Frames which are captured one after another, starting over after the last one
+pub struct ImageSequence {
+    frames: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl ImageSequence {
+    pub fn from_paths(paths: &[PathBuf]) -> Result<ImageSequence> {
+        if paths.is_empty() {
+            return Err(Error::Camera("Camera image sequence is empty"));
+        }
+        let frames = paths.iter().map(|path| load_png(path)).collect::<Result<Vec<_>>>()?;
+        Ok(ImageSequence { frames, next: 0 })
+    }
+
+    /// Loads the PNG files of a directory in the order of their names
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<ImageSequence> {
+        let entries = fs::read_dir(dir).map_err(|e| Error::CameraFile("Could not read the camera image directory", e))?;
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")))
+            .collect();
+        paths.sort();
+        ImageSequence::from_paths(&paths)
+    }
+}
+
+impl ImageSource for ImageSequence {
+    fn capture(&mut self) -> Vec<u8> {
+        let frame = self.frames[self.next].clone();
+        self.next = (self.next + 1) % self.frames.len();
+        frame
+    }
+}
+
+/// Decodes a PNG to grayscale and crops and scales it to fill the sensor
+fn load_png(path: &Path) -> Result<Vec<u8>> {
+    let file = fs::File::open(path).map_err(|e| Error::CameraFile("Could not open camera image", e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(Error::CameraImage)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(Error::CameraImage)?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+    let luma = |x: usize, y: usize| -> u8 {
+        let pixel = &buffer[y * info.line_size + x * channels..][..channels];
+        match channels {
+            1 | 2 => pixel[0],
+            _ => ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000) as u8,
+        }
+    };
+
+    // Keep the aspect ratio of the sensor by cutting off the excess on one side
+    let (crop_w, crop_h) = if width * SENSOR_H > height * SENSOR_W {
+        ((height * SENSOR_W / SENSOR_H).max(1), height)
+    } else {
+        (width, (width * SENSOR_H / SENSOR_W).max(1))
+    };
+    let (left, top) = ((width - crop_w) / 2, (height - crop_h) / 2);
+    let mut frame = Vec::with_capacity(SENSOR_W * SENSOR_H);
+    for y in 0..SENSOR_H {
+        for x in 0..SENSOR_W {
+            frame.push(luma(left + x * crop_w / SENSOR_W, top + y * crop_h / SENSOR_H));
+        }
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ImageSequence, ImageSource, StillImage, TestPattern, SENSOR_H, SENSOR_W};
+    use std::path::PathBuf;
+
+    fn write_png(name: &str, width: u32, height: u32, rgb: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rboy_camera_{}_{}.png", std::process::id(), name));
+        let mut encoder = png::Encoder::new(std::fs::File::create(&path).unwrap(), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(rgb).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_pattern_fills_sensor() {
+        let frame = TestPattern.capture();
+        assert_eq!(frame.len(), SENSOR_W * SENSOR_H);
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame[10 * SENSOR_W + 10], 20);
+        assert_eq!(frame[100 * SENSOR_W + 10], 235);
+    }
+
+    #[test]
+    fn loads_png_sequence() {
+        // A wide image, whose left and right thirds are cropped away
+        let mut rgb = Vec::new();
+        for _ in 0..2 {
+            for x in 0..6 {
+                rgb.extend_from_slice(if (2..4).contains(&x) { &[255, 255, 255] } else { &[255, 0, 0] });
+            }
+        }
+        let white = write_png("white", 6, 2, &rgb);
+        let black = write_png("black", 1, 1, &[0, 0, 0]);
+
+        let frame = StillImage::from_png(&white).unwrap().capture();
+        assert!(frame.iter().all(|&v| v == 255));
+
+        let mut sequence = ImageSequence::from_paths(&[white.clone(), black.clone()]).unwrap();
+        assert_eq!(sequence.capture()[0], 255);
+        assert_eq!(sequence.capture()[0], 0);
+        assert_eq!(sequence.capture()[0], 255);
+
+        let _ = std::fs::remove_file(white);
+        let _ = std::fs::remove_file(black);
+    }
+}
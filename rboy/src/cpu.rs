@@ -3,7 +3,7 @@ use crate::register::Registers;
 use crate::serial::SerialCallback;
 use crate::mmu::MMU;
 use crate::mbc;
-use crate::StrResult;
+use crate::{Error, Result};
 
 pub struct CPU<'a> {
     reg: Registers,
@@ -15,7 +15,7 @@ pub struct CPU<'a> {
 }
 
 impl<'a> CPU<'a> {
-    pub fn new(cart: Box<dyn mbc::MBC+'static>, serial_callback: Option<SerialCallback<'a>>) -> StrResult<CPU<'a>> {
+    pub fn new(cart: Box<dyn mbc::MBC+'static>, serial_callback: Option<SerialCallback<'a>>) -> Result<CPU<'a>> {
         let cpu_mmu = MMU::new(cart, serial_callback)?;
         let registers = Registers::new(cpu_mmu.gbmode);
         Ok(CPU {
@@ -28,7 +28,7 @@ impl<'a> CPU<'a> {
         })
     }
 
-    pub fn new_cgb(cart: Box<dyn mbc::MBC+'static>, serial_callback: Option<SerialCallback<'a>>) -> StrResult<CPU<'a>> {
+    pub fn new_cgb(cart: Box<dyn mbc::MBC+'static>, serial_callback: Option<SerialCallback<'a>>) -> Result<CPU<'a>> {
         let cpu_mmu = MMU::new_cgb(cart, serial_callback)?;
         let registers = Registers::new(cpu_mmu.gbmode);
         Ok(CPU {
@@ -41,9 +41,13 @@ impl<'a> CPU<'a> {
         })
     }
 
-    pub fn do_cycle(&mut self) -> u32 {
+    pub fn do_cycle(&mut self) -> Result<u32> {
         let ticks = self.docycle() * 4;
-        return self.mmu.do_cycle(ticks);
+        let ticks = self.mmu.do_cycle(ticks);
+        match self.mmu.take_fault() {
+            Some(error) => Err(error),
+            None => Ok(ticks),
+        }
     }
 
     fn docycle(&mut self) -> u32 {
@@ -69,7 +73,7 @@ impl<'a> CPU<'a> {
 
     fn fetchword(&mut self) -> u16 {
         let w = self.mmu.rw(self.reg.pc);
-        self.reg.pc += 2;
+        self.reg.pc = self.reg.pc.wrapping_add(2);
         w
     }
 
@@ -113,7 +117,7 @@ impl<'a> CPU<'a> {
 
     fn popstack(&mut self) -> u16 {
         let res = self.mmu.rw(self.reg.sp);
-        self.reg.sp += 2;
+        self.reg.sp = self.reg.sp.wrapping_add(2);
         res
     }
 
@@ -152,7 +156,7 @@ impl<'a> CPU<'a> {
             0x1D => { self.reg.e = self.alu_dec(self.reg.e); 1 },
             0x1E => { self.reg.e = self.fetchbyte(); 2 },
             0x1F => { self.reg.a = self.alu_rr(self.reg.a); self.reg.flag(Z, false); 1 },
-            0x20 => { if !self.reg.getflag(Z) { self.cpu_jr(); 3 } else { self.reg.pc += 1; 2 } },
+            0x20 => { if !self.reg.getflag(Z) { self.cpu_jr(); 3 } else { self.reg.pc = self.reg.pc.wrapping_add(1); 2 } },
             0x21 => { let v = self.fetchword(); self.reg.sethl(v); 3 },
             0x22 => { self.mmu.wb(self.reg.hli(), self.reg.a); 2 },
             0x23 => { let v = self.reg.hl().wrapping_add(1); self.reg.sethl(v); 2 },
@@ -160,7 +164,7 @@ impl<'a> CPU<'a> {
             0x25 => { self.reg.h = self.alu_dec(self.reg.h); 1 },
             0x26 => { self.reg.h = self.fetchbyte(); 2 },
             0x27 => { self.alu_daa(); 1 },
-            0x28 => { if self.reg.getflag(Z) { self.cpu_jr(); 3 } else { self.reg.pc += 1; 2  } },
+            0x28 => { if self.reg.getflag(Z) { self.cpu_jr(); 3 } else { self.reg.pc = self.reg.pc.wrapping_add(1); 2  } },
             0x29 => { let v = self.reg.hl(); self.alu_add16(v); 2 },
             0x2A => { self.reg.a = self.mmu.rb(self.reg.hli()); 2 },
             0x2B => { let v = self.reg.hl().wrapping_sub(1); self.reg.sethl(v); 2 },
@@ -168,7 +172,7 @@ impl<'a> CPU<'a> {
             0x2D => { self.reg.l = self.alu_dec(self.reg.l); 1 },
             0x2E => { self.reg.l = self.fetchbyte(); 2 },
             0x2F => { self.reg.a = !self.reg.a; self.reg.flag(H, true); self.reg.flag(N, true); 1 },
-            0x30 => { if !self.reg.getflag(C) { self.cpu_jr(); 3 } else { self.reg.pc += 1; 2 } },
+            0x30 => { if !self.reg.getflag(C) { self.cpu_jr(); 3 } else { self.reg.pc = self.reg.pc.wrapping_add(1); 2 } },
             0x31 => { self.reg.sp = self.fetchword(); 3 },
             0x32 => { self.mmu.wb(self.reg.hld(), self.reg.a); 2 },
             0x33 => { self.reg.sp = self.reg.sp.wrapping_add(1); 2 },
@@ -176,7 +180,7 @@ impl<'a> CPU<'a> {
             0x35 => { let a = self.reg.hl(); let v = self.mmu.rb(a); let v2 = self.alu_dec(v); self.mmu.wb(a, v2); 3 },
             0x36 => { let v = self.fetchbyte(); self.mmu.wb(self.reg.hl(), v); 3 },
             0x37 => { self.reg.flag(C, true); self.reg.flag(H, false); self.reg.flag(N, false); 1 },
-            0x38 => { if self.reg.getflag(C) { self.cpu_jr(); 3 } else { self.reg.pc += 1; 2  } },
+            0x38 => { if self.reg.getflag(C) { self.cpu_jr(); 3 } else { self.reg.pc = self.reg.pc.wrapping_add(1); 2  } },
             0x39 => { self.alu_add16(self.reg.sp); 2 },
             0x3A => { self.reg.a = self.mmu.rb(self.reg.hld()); 2 },
             0x3B => { self.reg.sp = self.reg.sp.wrapping_sub(1); 2 },
@@ -314,31 +318,31 @@ impl<'a> CPU<'a> {
             0xBF => { self.alu_cp(self.reg.a); 1 },
             0xC0 => { if !self.reg.getflag(Z) { self.reg.pc = self.popstack(); 5 } else { 2 } },
             0xC1 => { let v = self.popstack(); self.reg.setbc(v); 3 },
-            0xC2 => { if !self.reg.getflag(Z) { self.reg.pc = self.fetchword(); 4 } else { self.reg.pc += 2; 3 } },
+            0xC2 => { if !self.reg.getflag(Z) { self.reg.pc = self.fetchword(); 4 } else { self.reg.pc = self.reg.pc.wrapping_add(2); 3 } },
             0xC3 => { self.reg.pc = self.fetchword(); 4 },
-            0xC4 => { if !self.reg.getflag(Z) { self.pushstack(self.reg.pc + 2); self.reg.pc = self.fetchword(); 6 } else { self.reg.pc += 2; 3 } },
+            0xC4 => { if !self.reg.getflag(Z) { self.pushstack(self.reg.pc.wrapping_add(2)); self.reg.pc = self.fetchword(); 6 } else { self.reg.pc = self.reg.pc.wrapping_add(2); 3 } },
             0xC5 => { self.pushstack(self.reg.bc()); 4 },
             0xC6 => { let v = self.fetchbyte(); self.alu_add(v, false); 2 },
             0xC7 => { self.pushstack(self.reg.pc); self.reg.pc = 0x00; 4 },
             0xC8 => { if self.reg.getflag(Z) { self.reg.pc = self.popstack(); 5 } else { 2 } },
             0xC9 => { self.reg.pc = self.popstack(); 4 },
-            0xCA => { if self.reg.getflag(Z) { self.reg.pc = self.fetchword(); 4 } else { self.reg.pc += 2; 3 } },
+            0xCA => { if self.reg.getflag(Z) { self.reg.pc = self.fetchword(); 4 } else { self.reg.pc = self.reg.pc.wrapping_add(2); 3 } },
             0xCB => { self.call_cb() },
-            0xCC => { if self.reg.getflag(Z) { self.pushstack(self.reg.pc + 2); self.reg.pc = self.fetchword(); 6 } else { self.reg.pc += 2; 3 } },
-            0xCD => { self.pushstack(self.reg.pc + 2); self.reg.pc = self.fetchword(); 6 },
+            0xCC => { if self.reg.getflag(Z) { self.pushstack(self.reg.pc.wrapping_add(2)); self.reg.pc = self.fetchword(); 6 } else { self.reg.pc = self.reg.pc.wrapping_add(2); 3 } },
+            0xCD => { self.pushstack(self.reg.pc.wrapping_add(2)); self.reg.pc = self.fetchword(); 6 },
             0xCE => { let v = self.fetchbyte(); self.alu_add(v, true); 2 },
             0xCF => { self.pushstack(self.reg.pc); self.reg.pc = 0x08; 4 },
             0xD0 => { if !self.reg.getflag(C) { self.reg.pc = self.popstack(); 5 } else { 2 } },
             0xD1 => { let v = self.popstack(); self.reg.setde(v); 3 },
-            0xD2 => { if !self.reg.getflag(C) { self.reg.pc = self.fetchword(); 4 } else { self.reg.pc += 2; 3 } },
-            0xD4 => { if !self.reg.getflag(C) { self.pushstack(self.reg.pc + 2); self.reg.pc = self.fetchword(); 6 } else { self.reg.pc += 2; 3 } },
+            0xD2 => { if !self.reg.getflag(C) { self.reg.pc = self.fetchword(); 4 } else { self.reg.pc = self.reg.pc.wrapping_add(2); 3 } },
+            0xD4 => { if !self.reg.getflag(C) { self.pushstack(self.reg.pc.wrapping_add(2)); self.reg.pc = self.fetchword(); 6 } else { self.reg.pc = self.reg.pc.wrapping_add(2); 3 } },
             0xD5 => { self.pushstack(self.reg.de()); 4 },
             0xD6 => { let v = self.fetchbyte(); self.alu_sub(v, false); 2 },
             0xD7 => { self.pushstack(self.reg.pc); self.reg.pc = 0x10; 4 },
             0xD8 => { if self.reg.getflag(C) { self.reg.pc = self.popstack(); 5 } else { 2 } },
             0xD9 => { self.reg.pc = self.popstack(); self.setei = 1; 4 },
-            0xDA => { if self.reg.getflag(C) { self.reg.pc = self.fetchword(); 4 } else { self.reg.pc += 2; 3 } },
-            0xDC => { if self.reg.getflag(C) { self.pushstack(self.reg.pc + 2); self.reg.pc = self.fetchword(); 6 } else { self.reg.pc += 2; 3 } },
+            0xDA => { if self.reg.getflag(C) { self.reg.pc = self.fetchword(); 4 } else { self.reg.pc = self.reg.pc.wrapping_add(2); 3 } },
+            0xDC => { if self.reg.getflag(C) { self.pushstack(self.reg.pc.wrapping_add(2)); self.reg.pc = self.fetchword(); 6 } else { self.reg.pc = self.reg.pc.wrapping_add(2); 3 } },
             0xDE => { let v = self.fetchbyte(); self.alu_sub(v, true); 2 },
             0xDF => { self.pushstack(self.reg.pc); self.reg.pc = 0x18; 4 },
             0xE0 => { let a = 0xFF00 | self.fetchbyte() as u16; self.mmu.wb(a, self.reg.a); 3 },
@@ -365,7 +369,12 @@ impl<'a> CPU<'a> {
             0xFB => { self.setei = 2; 1 },
             0xFE => { let v = self.fetchbyte(); self.alu_cp(v); 2 },
             0xFF => { self.pushstack(self.reg.pc); self.reg.pc = 0x38; 4 },
-            other=> panic!("Instruction {:2X} is not implemented", other),
+            other => {
+                // The CPU locks up on opcodes which do not exist, so it stays on this one
+                self.reg.pc = self.reg.pc.wrapping_sub(1);
+                self.mmu.report_fault(Error::IllegalInstruction { opcode: other, address: self.reg.pc });
+                1
+            },
         }
     }
 
@@ -815,71 +824,94 @@ impl<'a> CPU<'a> {
     }
 }
 
-// #[cfg(test)]
-// mod test
-// {
-//     use super::CPU;
-//     use crate::mbc;
-//
-//     const CPUINSTRS: &'static str = "roms/cpu_instrs.gb";
-//     const CPU_SERIAL: &'static [u8] = b"cpu_instrs\n\n01:ok  02:ok  03:ok  04:ok  05:ok  06:ok  07:ok  08:ok  09:ok  10:ok  11:ok  \n\nPassed all tests\n";
-//     const GPU_CLASSIC_CHECKSUM: u32 = 3112234583;
-//     const GPU_COLOR_CHECKSUM: u32 = 938267576;
-//
-//     #[test]
-//     fn cpu_instrs_classic()
-//     {
-//         let mut sum_classic = 0_u32;
-//         let mut output = Vec::new();
-//
-//         {
-//             let serial = |v: u8| { output.push(v); None };
-//             let cart = mbc::FileBackedMBC::new(CPUINSTRS.into(), false).unwrap();
-//             let mut c = match CPU::new(Box::new(cart), Some(Box::new(serial)))
-//             {
-//                 Err(message) => { panic!("{}", message); },
-//                 Ok(cpu) => cpu,
-//             };
-//             let mut ticks = 0;
-//             while ticks < 63802933 * 4
-//             {
-//                 ticks += c.do_cycle();
-//             }
-//             for i in 0 .. c.mmu.gpu.data.len()
-//             {
-//                 sum_classic = sum_classic.wrapping_add((c.mmu.gpu.data[i] as u32).wrapping_mul(i as u32));
-//             }
-//         }
-//
-//         assert!(&*output == CPU_SERIAL, "Serial did not output the expected result");
-//         assert!(sum_classic == GPU_CLASSIC_CHECKSUM, "GPU did not produce expected graphics");
-//     }
-//
-//     #[test]
-//     fn cpu_instrs_color() {
-//         let mut sum_color = 0_u32;
-//         let mut output = Vec::new();
-//
-//         {
-//             let serial = |v| { output.push(v); None };
-//             let cart = mbc::FileBackedMBC::new(CPUINSTRS.into(), false).unwrap();
-//             let mut c = match CPU::new_cgb(Box::new(cart), Some(Box::new(serial)))
-//             {
-//                 Err(message) => { panic!("{}", message); },
-//                 Ok(cpu) => cpu,
-//             };
-//             let mut ticks = 0;
-//             while ticks < 63802933 * 2
-//             {
-//                 ticks += c.do_cycle();
-//             }
-//             for i in 0 .. c.mmu.gpu.data.len()
-//             {
-//                 sum_color = sum_color.wrapping_add((c.mmu.gpu.data[i] as u32).wrapping_mul(i as u32));
-//             }
-//         }
-//
-//         assert!(&*output == CPU_SERIAL, "Serial did not output the expected result");
-//         assert!(sum_color == GPU_COLOR_CHECKSUM, "GPU did not produce expected graphics");
-//     }
-// }
+#[cfg(test)]
+mod test
+{
+    use super::CPU;
+    use crate::mbc;
+    use crate::Error;
+
+    const CPUINSTRS: &'static str = "roms/cpu_instrs.gb";
+    const CPU_SERIAL: &'static [u8] = b"cpu_instrs\n\n01:ok  02:ok  03:ok  04:ok  05:ok  06:ok  07:ok  08:ok  09:ok  10:ok  11:ok  \n\nPassed all tests\n";
+    const GPU_CLASSIC_CHECKSUM: u32 = 3112234583;
+    const GPU_COLOR_CHECKSUM: u32 = 938267576;
+
+    #[test]
+    fn bad_games_stop_with_errors() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        // HDMA from the unusable E000, then the opcode D3, which does not exist
+        rom[0x100..0x10A].copy_from_slice(&[0x3E, 0xE0, 0xE0, 0x51, 0x3E, 0x00, 0xE0, 0x55, 0xD3, 0x00]);
+        let cart = mbc::get_mbc(&mut mbc::MemoryCartridge::new(rom), true).unwrap();
+        let mut c = CPU::new_cgb(cart, None).unwrap();
+
+        let mut result = Ok(0);
+        while result.is_ok() {
+            result = c.do_cycle();
+        }
+        assert!(matches!(result, Err(Error::IllegalHdmaSource(0xE000))));
+        // The CPU stays locked up on the illegal opcode
+        for _ in 0..2 {
+            assert!(matches!(c.do_cycle(), Err(Error::IllegalInstruction { opcode: 0xD3, address: 0x108 })));
+        }
+    }
+
+    #[test]
+    #[ignore = "requires roms/cpu_instrs.gb"]
+    fn cpu_instrs_classic()
+    {
+        let mut sum_classic = 0_u32;
+        let mut output = Vec::new();
+
+        {
+            let serial = |v: u8| { output.push(v); None };
+            let cart = mbc::FileBackedMBC::new(&mut mbc::FileCartridge::new(CPUINSTRS), false).unwrap();
+            let mut c = match CPU::new(Box::new(cart), Some(Box::new(serial)))
+            {
+                Err(message) => { panic!("{}", message); },
+                Ok(cpu) => cpu,
+            };
+            let mut ticks = 0;
+            while ticks < 63802933 * 4
+            {
+                ticks += c.do_cycle().unwrap();
+            }
+            for i in 0 .. c.mmu.gpu.data.len()
+            {
+                sum_classic = sum_classic.wrapping_add((c.mmu.gpu.data[i] as u32).wrapping_mul(i as u32));
+            }
+        }
+
+        assert!(&*output == CPU_SERIAL, "Serial did not output the expected result");
+        assert!(sum_classic == GPU_CLASSIC_CHECKSUM, "GPU did not produce expected graphics");
+    }
+
+    #[test]
+    #[ignore = "requires roms/cpu_instrs.gb"]
+    fn cpu_instrs_color() {
+        let mut sum_color = 0_u32;
+        let mut output = Vec::new();
+
+        {
+            let serial = |v| { output.push(v); None };
+            let cart = mbc::FileBackedMBC::new(&mut mbc::FileCartridge::new(CPUINSTRS), false).unwrap();
+            let mut c = match CPU::new_cgb(Box::new(cart), Some(Box::new(serial)))
+            {
+                Err(message) => { panic!("{}", message); },
+                Ok(cpu) => cpu,
+            };
+            let mut ticks = 0;
+            while ticks < 63802933 * 2
+            {
+                ticks += c.do_cycle().unwrap();
+            }
+            for i in 0 .. c.mmu.gpu.data.len()
+            {
+                sum_color = sum_color.wrapping_add((c.mmu.gpu.data[i] as u32).wrapping_mul(i as u32));
+            }
+        }
+
+        assert!(&*output == CPU_SERIAL, "Serial did not output the expected result");
+        assert!(sum_color == GPU_COLOR_CHECKSUM, "GPU did not produce expected graphics");
+    }
+}
@@ -1,10 +1,12 @@
+use crate::camera::ImageSource;
 use crate::cpu::CPU;
 use crate::gbmode::GbMode;
 use crate::keypad::KeypadKey;
 use crate::printer::GbPrinter;
+use crate::rtc::RtcMode;
 use crate::mbc;
 use crate::sound;
-use crate::StrResult;
+use crate::Result;
 
 pub struct Device {
     cpu: CPU<'static>,
@@ -20,12 +22,22 @@ fn stdoutprinter(v: u8) -> Option<u8> {
 }
 
 impl Device {
-    pub fn new_cgb() -> StrResult<Device> {
-        let cart = mbc::FileBackedMBC::new(true)?;
+    pub fn new_cgb(romname: &str, skip_checksum: bool) -> Result<Device> {
+        Device::new_cgb_from_source(&mut mbc::FileCartridge::new(romname), skip_checksum)
+    }
+
+    pub fn new_cgb_from_source(source: &mut dyn mbc::CartridgeSource, skip_checksum: bool) -> Result<Device> {
+        let cart = mbc::FileBackedMBC::new(source, skip_checksum)?;
+        CPU::new_cgb(Box::new(cart), None).map(|cpu| Device { cpu: cpu })
+    }
+
+    pub fn new_cgb_live(bus: &gbcart::BusConfig) -> Result<Device> {
+        let cart = mbc::HardwareMBC::new(bus)?;
         CPU::new_cgb(Box::new(cart), None).map(|cpu| Device { cpu: cpu })
     }
 
-    pub fn do_cycle(&mut self) -> u32 {
+    /// Runs one instruction and returns the ticks it took, or the error the game ran into
+    pub fn do_cycle(&mut self) -> Result<u32> {
         self.cpu.do_cycle()
     }
 
@@ -83,11 +95,40 @@ impl Device {
         self.cpu.mmu.keypad.keydown(key);
     }
 
+    /// Tilts cartridges with an accelerometer, such as MBC7, by `x` and `y` times the gravity.
+    /// Positive values tilt the Game Boy to the right and away from the player.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cpu.mmu.mbc.set_tilt(x, y);
+    }
+
+    /// Sets what the Game Boy Camera sees, other cartridges ignore it
+    pub fn set_camera_source(&mut self, source: Box<dyn ImageSource>) {
+        self.cpu.mmu.mbc.set_image_source(source);
+    }
+
+    pub fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.cpu.mmu.mbc.set_rtc_mode(mode);
+    }
+
+    /// Sets the clock of the cartridge to `seconds` after day 0
+    pub fn set_rtc(&mut self, seconds: u64) {
+        self.cpu.mmu.mbc.set_rtc(seconds);
+    }
+
+    pub fn advance_rtc(&mut self, seconds: u64) {
+        self.cpu.mmu.mbc.advance_rtc(seconds);
+    }
+
+    /// Calls `callback` whenever the motor of a rumble cartridge starts or stops
+    pub fn on_rumble<F: FnMut(bool) + Send + 'static>(&mut self, callback: F) {
+        self.cpu.mmu.mbc.set_rumble_callback(Box::new(callback));
+    }
+
     pub fn romname(&mut self) -> String {
         self.cpu.mmu.mbc.romname()
     }
 
-    pub fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    pub fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         self.cpu.mmu.mbc.loadram(ramdata)
     }
 
@@ -95,6 +136,11 @@ impl Device {
         self.cpu.mmu.mbc.dumpram()
     }
 
+    /// Writes the save RAM to the save file right away, instead of waiting for the autosave
+    pub fn save_ram(&mut self) -> Result<()> {
+        self.cpu.mmu.mbc.save()
+    }
+
     pub fn ram_is_battery_backed(&self) -> bool {
         self.cpu.mmu.mbc.is_battery_backed()
     }
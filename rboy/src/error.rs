@@ -0,0 +1,81 @@
+//! Errors of the emulator, from loading a cartridge to running a game.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The ROM is not a cartridge
+    Rom(&'static str),
+    /// The ROM file could not be read
+    RomFile(io::Error),
+    /// The header checksum does not match the header
+    InvalidChecksum,
+    /// The cartridge type in the header is not emulated
+    UnsupportedMbc(u8),
+    /// The game needs a Game Boy Color
+    RequiresColor,
+    /// The cartridge slot could not be read
+    Cartridge(&'static str),
+    /// The cartridge reader failed
+    Reader(gbcart::Error),
+    /// A save does not fit the cartridge
+    Save(&'static str),
+    /// A save file could not be read or written
+    SaveFile(&'static str, PathBuf, io::Error),
+    /// There is no save to export at the path
+    NoSave(PathBuf),
+    /// An image for the camera sensor could not be used
+    Camera(&'static str),
+    /// An image for the camera sensor could not be read
+    CameraFile(&'static str, io::Error),
+    /// An image for the camera sensor is not a valid PNG
+    CameraImage(png::DecodingError),
+    /// The game ran an opcode which does not exist, which locks up the CPU
+    IllegalInstruction { opcode: u8, address: u16 },
+    /// The game started an HDMA transfer from an address it can not copy from
+    IllegalHdmaSource(u16),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Rom(message) | Error::Cartridge(message) | Error::Save(message) | Error::Camera(message) => {
+                f.write_str(message)
+            },
+            Error::RomFile(ref e) => write!(f, "Could not read the ROM: {}", e),
+            Error::Reader(ref e) => write!(f, "{}", e),
+            Error::SaveFile(message, ref path, ref e) => write!(f, "{} {}: {}", message, path.display(), e),
+            Error::NoSave(ref path) => write!(f, "There is no save of this game at {}", path.display()),
+            Error::CameraFile(message, ref e) => write!(f, "{}: {}", message, e),
+            Error::CameraImage(ref e) => write!(f, "Camera image is not a valid PNG: {}", e),
+            Error::InvalidChecksum => f.write_str("Cartridge checksum is invalid"),
+            Error::UnsupportedMbc(cartridge_type) => write!(f, "Unsupported MBC type {:02X}", cartridge_type),
+            Error::RequiresColor => f.write_str("This game does not work in Classic mode"),
+            Error::IllegalInstruction { opcode, address } => {
+                write!(f, "Instruction {:02X} at {:04X} is not implemented", opcode, address)
+            },
+            Error::IllegalHdmaSource(source) => write!(f, "HDMA transfer with illegal start address {:04X}", source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::RomFile(ref e) | Error::SaveFile(_, _, ref e) | Error::CameraFile(_, ref e) => Some(e),
+            Error::Reader(ref e) => Some(e),
+            Error::CameraImage(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<gbcart::Error> for Error {
+    fn from(e: gbcart::Error) -> Error {
+        Error::Reader(e)
+    }
+}
@@ -1,8 +1,13 @@
+pub use crate::error::{Error, Result};
 pub use crate::keypad::KeypadKey;
 pub use crate::gpu::{SCREEN_W, SCREEN_H};
 pub use crate::sound::AudioPlayer;
+pub use crate::mbc::{CartridgeSource, DatCheckedCartridge, FileCartridge, GpioCartridge, MemoryCartridge, MockCartridge};
 
+pub mod camera;
 pub mod device;
+pub mod error;
+pub mod rtc;
 
 mod cpu;
 mod gbmode;
@@ -16,11 +21,11 @@ mod serial;
 mod sound;
 mod timer;
 
-pub type StrResult<T> = Result<T, &'static str>;
-
 use device::Device;
+use rtc::RtcMode;
 use std::io::{self, Read};
 use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use cpal::traits::{HostTrait, DeviceTrait, StreamTrait};
@@ -29,17 +34,74 @@ use glium::winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
 
 const EXITCODE_SUCCESS : i32 = 0;
 const EXITCODE_CPULOADFAILS : i32 = 2;
+const EXITCODE_INFOFAILS : i32 = 3;
+const EXITCODE_SAVEFAILS : i32 = 4;
+const EXITCODE_GAMEFAILS : i32 = 5;
 
 #[derive(Default)]
 struct RenderOptions {
     pub linear_interpolation: bool,
 }
 
+/// Where the game is loaded from
+struct CartridgeOptions<'a> {
+    filename: Option<&'a str>,
+    live: bool,
+    skip_checksum: bool,
+    bus: gbcart::BusConfig,
+    dat: Option<&'a gbcart::Dat>,
+    rtc: RtcMode,
+    save_dir: Option<&'a str>,
+}
+
 enum GBEvent {
     KeyUp(KeypadKey),
     KeyDown(KeypadKey),
     SpeedUp,
     SpeedDown,
+    Tilt(f32, f32),
+}
+
+/// Maps the keyboard and mouse to the accelerometer of MBC7 cartridges.
+/// I, J, K and L tilt by 1 g, dragging with the left button tilts towards the cursor.
+#[derive(Default)]
+struct TiltControl {
+    left: bool,
+    right: bool,
+    away: bool,
+    towards: bool,
+    dragging: bool,
+    cursor: (f32, f32),
+}
+
+impl TiltControl {
+    /// Updates the state of a tilt key, returns false for other keys
+    fn key(&mut self, key: &glium::winit::keyboard::Key<&str>, pressed: bool) -> bool {
+        use glium::winit::keyboard::Key;
+        let state = match *key {
+            Key::Character("J" | "j") => &mut self.left,
+            Key::Character("L" | "l") => &mut self.right,
+            Key::Character("I" | "i") => &mut self.away,
+            Key::Character("K" | "k") => &mut self.towards,
+            _ => return false,
+        };
+        *state = pressed;
+        true
+    }
+
+    /// Moves the cursor to `x` and `y`, relative to the window from -1 to 1
+    fn cursor(&mut self, position: glium::winit::dpi::PhysicalPosition<f64>, size: glium::winit::dpi::PhysicalSize<u32>) {
+        let relative = |pos: f64, len: u32| ((pos / len.max(1) as f64) * 2.0 - 1.0).clamp(-1.0, 1.0) as f32;
+        self.cursor = (relative(position.x, size.width), -relative(position.y, size.height));
+    }
+
+    fn tilt(&self) -> (f32, f32) {
+        if self.dragging {
+            return self.cursor;
+        }
+        let axis = |negative: bool, positive: bool| positive as i8 as f32 - negative as i8 as f32;
+        (axis(self.left, self.right), axis(self.towards, self.away))
+    }
 }
 
 #[cfg(target_os = "windows")]
@@ -85,6 +147,26 @@ fn parse_scale_var(arg: &str) -> Result<u32, ArgParseError> {
     }
 }
 
+fn parse_bus_config(path: &str) -> Result<gbcart::BusConfig, ArgParseError> {
+    gbcart::BusConfig::from_file(path).map_err(|e| ArgParseError::new(e.to_string()))
+}
+
+fn parse_bus_delay(arg: &str) -> Result<gbcart::BusTiming, ArgParseError> {
+    gbcart::BusTiming::parse(arg).map_err(|e| ArgParseError::new(e.to_string()))
+}
+
+fn parse_dat(path: &str) -> Result<gbcart::Dat, ArgParseError> {
+    gbcart::Dat::from_file(path).map_err(|e| ArgParseError::new(e.to_string()))
+}
+
+fn parse_rtc_mode(arg: &str) -> Result<RtcMode, ArgParseError> {
+    match arg {
+        "wall" => Ok(RtcMode::WallClock),
+        "emulated" => Ok(RtcMode::Emulated),
+        _ => Err(ArgParseError::new("RTC mode must be wall or emulated")),
+    }
+}
+
 fn main() {
     let exit_status = real_main();
     if exit_status != EXITCODE_SUCCESS {
@@ -107,6 +189,10 @@ fn real_main() -> i32 {
             .short('p')
             .long("printer")
             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("camera")
+            .help("Sets what the Game Boy Camera sees: a PNG file, a directory of PNG files or test for a test pattern")
+            .long("camera")
+            .value_name("SOURCE"))
         .arg(clap::Arg::new("scale")
             .help("Sets the scale of the interface. Default: 4")
             .short('x')
@@ -121,19 +207,114 @@ fn real_main() -> i32 {
             .help("Starts the emulator in a special test mode")
             .long("test-mode")
             .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("skip-checksum")
+            .help("Skips verification of the cartridge checksum")
+            .long("skip-checksum")
+            .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("live")
+            .help("Runs the game directly from the cartridge on the GPIO bus instead of dumping it first")
+            .long("live")
+            .conflicts_with("filename")
+            .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("bus-config")
+            .help("Reads the pin assignments and bus delay of the cartridge adapter from FILE")
+            .long("bus-config")
+            .value_name("FILE")
+            .value_parser(parse_bus_config))
+        .arg(clap::Arg::new("bus-delay")
+            .help("Sets the cartridge bus delay in nanoseconds, or auto to find the fastest reliable one")
+            .long("bus-delay")
+            .value_name("NS")
+            .value_parser(parse_bus_delay))
+        .arg(clap::Arg::new("dat")
+            .help("Looks the loaded ROM up in a No-Intro style XML DAT FILE")
+            .long("dat")
+            .value_name("FILE")
+            .value_parser(parse_dat))
+        .arg(clap::Arg::new("rtc")
+            .help("Drives the cartridge clock from the wall clock or from the emulated CPU cycles. Default: wall")
+            .long("rtc")
+            .value_name("MODE")
+            .value_parser(parse_rtc_mode))
+        .arg(clap::Arg::new("save-dir")
+            .help("Keeps battery saves in DIR. Default: the rboy/saves directory in the user data directory")
+            .long("save-dir")
+            .value_name("DIR"))
+        .arg(clap::Arg::new("filename")
+            .help("Sets the ROM file to load. The cartridge is read over GPIO when omitted")
+            .required(false))
+        .subcommand(clap::Command::new("info")
+            .about("Prints the cartridge header of a ROM file")
+            .arg(clap::Arg::new("rom")
+                .help("The ROM file to inspect")
+                .required(true))
+            .arg(clap::Arg::new("json")
+                .help("Prints the header as JSON")
+                .long("json")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(clap::Command::new("save")
+            .about("Converts between rboy saves and the raw .sav or .srm saves of other emulators")
+            .subcommand_required(true)
+            .arg(clap::Arg::new("save-dir")
+                .help("Looks for the rboy save in DIR. Default: the rboy/saves directory in the user data directory")
+                .long("save-dir")
+                .value_name("DIR")
+                .global(true))
+            .subcommand(clap::Command::new("import")
+                .about("Replaces the save of ROM with the raw save FILE, keeping the old one as a backup")
+                .arg(clap::Arg::new("rom")
+                    .help("The ROM file of the game")
+                    .required(true))
+                .arg(clap::Arg::new("file")
+                    .help("The raw save to import")
+                    .required(true)))
+            .subcommand(clap::Command::new("export")
+                .about("Writes the save of ROM to FILE as a raw save")
+                .arg(clap::Arg::new("rom")
+                    .help("The ROM file of the game")
+                    .required(true))
+                .arg(clap::Arg::new("file")
+                    .help("Where to write the raw save")
+                    .required(true))
+                .arg(clap::Arg::new("no-rtc")
+                    .help("Leaves out the clock footer of MBC3 saves, for flash cartridges which expect only the RAM")
+                    .long("no-rtc")
+                    .action(clap::ArgAction::SetTrue))))
+        .args_conflicts_with_subcommands(true)
         .get_matches();
 
+    if let Some(info) = matches.subcommand_matches("info") {
+        let json = info.get_one::<bool>("json").copied().unwrap();
+        return run_info(info.get_one::<String>("rom").unwrap(), json);
+    }
+    if let Some(save) = matches.subcommand_matches("save") {
+        return run_save(save);
+    }
+
     let test_mode = matches.get_one::<bool>("test-mode").copied().unwrap();
     let opt_serial = matches.get_one::<bool>("serial").copied().unwrap();
     let opt_printer = matches.get_one::<bool>("printer").copied().unwrap();
+    let opt_camera = matches.get_one::<String>("camera").map(|s| s.as_str());
     let opt_audio = matches.get_one::<bool>("audio").copied().unwrap();
     let scale = matches.get_one::<u32>("scale").copied().unwrap_or(4);
+    let mut cartridge = CartridgeOptions {
+        filename: matches.get_one::<String>("filename").map(|s| s.as_str()),
+        live: matches.get_one::<bool>("live").copied().unwrap(),
+        skip_checksum: matches.get_one::<bool>("skip-checksum").copied().unwrap(),
+        bus: matches.get_one::<gbcart::BusConfig>("bus-config").cloned().unwrap_or_default(),
+        dat: matches.get_one::<gbcart::Dat>("dat"),
+        rtc: matches.get_one::<RtcMode>("rtc").copied().unwrap_or(RtcMode::WallClock),
+        save_dir: matches.get_one::<String>("save-dir").map(|s| s.as_str()),
+    };
+    if let Some(timing) = matches.get_one::<gbcart::BusTiming>("bus-delay") {
+        cartridge.bus.timing = *timing;
+    }
 
     if test_mode {
-        return run_test_mode();
+        return run_test_mode(&cartridge, opt_printer, opt_camera);
     }
 
-    let cpu = construct_cpu(opt_serial, opt_printer);
+    let cpu = construct_cpu(&cartridge, opt_serial, opt_printer, opt_camera);
     if cpu.is_none() { return EXITCODE_CPULOADFAILS; }
     let mut cpu = cpu.unwrap();
 
@@ -153,6 +334,12 @@ fn real_main() -> i32 {
     }
     let romname = cpu.romname();
 
+    // Shown in the title bar, as there is no motor to drive
+    let rumble = Arc::new(AtomicBool::new(false));
+    let rumble_state = rumble.clone();
+    cpu.on_rumble(move |on| rumble_state.store(on, Ordering::Relaxed));
+    let mut rumble_shown = false;
+
     let (sender1, receiver1) = mpsc::channel();
     let (sender2, receiver2) = mpsc::sync_channel(1);
 
@@ -172,6 +359,7 @@ fn real_main() -> i32 {
         .unwrap();
 
     let mut renderoptions = <RenderOptions as Default>::default();
+    let mut tilt = TiltControl::default();
 
     let cputhread = thread::spawn(move|| run_cpu(cpu, sender2, receiver1));
 
@@ -181,6 +369,7 @@ fn real_main() -> i32 {
         let status = event_loop.pump_events(timeout, |ev, elwt| {
             use glium::winit::event::{Event, WindowEvent};
             use glium::winit::event::ElementState::{Pressed, Released};
+            use glium::winit::event::MouseButton;
             use glium::winit::keyboard::{Key, NamedKey};
 
             match ev {
@@ -200,6 +389,10 @@ fn real_main() -> i32 {
                         => { let _ = sender1.send(GBEvent::SpeedDown); },
                         (Pressed, Key::Character("t" | "T"))
                         => { renderoptions.linear_interpolation = !renderoptions.linear_interpolation; }
+                        (state, winitkey) if tilt.key(&winitkey, state == Pressed) => {
+                            let (x, y) = tilt.tilt();
+                            let _ = sender1.send(GBEvent::Tilt(x, y));
+                        },
                         (Pressed, winitkey) => {
                             if let Some(key) = winit_to_keypad(winitkey) {
                                 let _ = sender1.send(GBEvent::KeyDown(key));
@@ -211,6 +404,18 @@ fn real_main() -> i32 {
                             }
                         },
                     },
+                    WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => {
+                        tilt.dragging = state == Pressed;
+                        let (x, y) = tilt.tilt();
+                        let _ = sender1.send(GBEvent::Tilt(x, y));
+                    },
+                    WindowEvent::CursorMoved { position, .. } => {
+                        tilt.cursor(position, window.inner_size());
+                        if tilt.dragging {
+                            let (x, y) = tilt.tilt();
+                            let _ = sender1.send(GBEvent::Tilt(x, y));
+                        }
+                    },
                     _ => (),
                 },
                 _ => (),
@@ -221,16 +426,27 @@ fn real_main() -> i32 {
             break 'evloop;
         }
         match receiver2.recv() {
-            Ok(data) => recalculate_screen(&display, &mut texture, &*data, &renderoptions),
+            Ok(data) => {
+                recalculate_screen(&display, &mut texture, &*data, &renderoptions);
+                let rumbling = rumble.load(Ordering::Relaxed);
+                if rumbling != rumble_shown {
+                    let suffix = if rumbling { " (rumble)" } else { "" };
+                    window.set_title(&format!("RBoy - {}{}", romname, suffix));
+                    rumble_shown = rumbling;
+                }
+            },
             Err(..) => break 'evloop, // Remote end has hung-up
         }
     }
 
     drop(cpal_audio_stream);
     drop(receiver2); // Stop CPU thread by disconnecting
-    let _ = cputhread.join();
-
-    EXITCODE_SUCCESS
+    match cputhread.join() {
+        Ok(Ok(())) => EXITCODE_SUCCESS,
+        Ok(Err(error)) => { warn(error); EXITCODE_GAMEFAILS },
+        // The panic message was already printed
+        Err(..) => EXITCODE_GAMEFAILS,
+    }
 }
 
 fn winit_to_keypad(key: glium::winit::keyboard::Key<&str>) -> Option<KeypadKey> {
@@ -292,18 +508,50 @@ fn recalculate_screen<T: glium::glutin::surface::SurfaceTypeTrait + glium::gluti
     target.finish().unwrap();
 }
 
-fn warn(message: &str) {
+fn warn<M: std::fmt::Display>(message: M) {
     eprintln!("{}", message);
 }
 
-fn construct_cpu(output_serial: bool, output_printer: bool) -> Option<Box<Device>> {
-    let opt_c = Device::new_cgb();
+fn load_device(options: &CartridgeOptions) -> Result<Device> {
+    let (mut source, skip_checksum): (Box<dyn CartridgeSource>, bool) = match options.filename {
+        Some(romname) => {
+            let mut cartridge = FileCartridge::new(romname);
+            if let Some(dir) = options.save_dir {
+                cartridge = cartridge.with_save_dir(dir);
+            }
+            (Box::new(cartridge), options.skip_checksum)
+        },
+        None if options.live => return Device::new_cgb_live(&options.bus),
+        // The dump was already verified against the checksums
+        None => {
+            let mut cartridge = GpioCartridge::new().with_bus_config(options.bus.clone());
+            if let Some(dir) = options.save_dir {
+                cartridge = cartridge.with_save_dir(dir);
+            }
+            (Box::new(cartridge), true)
+        },
+    };
+    let mut device = match options.dat {
+        Some(dat) => Device::new_cgb_from_source(&mut DatCheckedCartridge::new(&mut *source, dat), skip_checksum)?,
+        None => Device::new_cgb_from_source(&mut *source, skip_checksum)?,
+    };
+    device.set_rtc_mode(options.rtc);
+    Ok(device)
+}
+
+fn construct_cpu(options: &CartridgeOptions, output_serial: bool, output_printer: bool, camera: Option<&str>) -> Option<Box<Device>> {
+    let opt_c = load_device(options);
     let mut c = match opt_c
     {
         Ok(cpu) => { cpu },
         Err(message) => { warn(message); return None; },
     };
 
+    if let Err(message) = attach_camera(&mut c, camera) {
+        warn(message);
+        return None;
+    }
+
     if output_printer {
         c.attach_printer();
     }
@@ -314,7 +562,8 @@ fn construct_cpu(output_serial: bool, output_printer: bool) -> Option<Box<Device
     Some(Box::new(c))
 }
 
-fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver<GBEvent>) {
+/// Runs the game until the window closes, or until the game fails with the returned error
+fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver<GBEvent>) -> Result<()> {
     let periodic = timer_periodic(16);
     let mut limit_speed = true;
 
@@ -323,7 +572,7 @@ fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver
 
     'outer: loop {
         while ticks < waitticks {
-            ticks += cpu.do_cycle();
+            ticks += cpu.do_cycle()?;
             if cpu.check_and_reset_gpu_updated() {
                 let data = cpu.get_gpu_data().to_vec();
                 if let Err(TrySendError::Disconnected(..)) = sender.try_send(data) {
@@ -342,6 +591,7 @@ fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver
                         GBEvent::KeyDown(key) => cpu.keydown(key),
                         GBEvent::SpeedUp => limit_speed = false,
                         GBEvent::SpeedDown => { limit_speed = true; cpu.sync_audio(); }
+                        GBEvent::Tilt(x, y) => cpu.set_tilt(x, y),
                     }
                 },
                 Err(TryRecvError::Empty) => break 'recv,
@@ -351,6 +601,7 @@ fn run_cpu(mut cpu: Box<Device>, sender: SyncSender<Vec<u8>>, receiver: Receiver
 
         if limit_speed { let _ = periodic.recv(); }
     }
+    Ok(())
 }
 
 fn timer_periodic(ms: u64) -> Receiver<()> {
@@ -437,7 +688,7 @@ impl CpalPlayer {
             cpal::SampleFormat::U64 => device.build_output_stream(&config, move|data: &mut [u64], _callback_info: &cpal::OutputCallbackInfo| cpal_thread(data, &stream_buffer), err_fn, None),
             cpal::SampleFormat::F32 => device.build_output_stream(&config, move|data: &mut [f32], _callback_info: &cpal::OutputCallbackInfo| cpal_thread(data, &stream_buffer), err_fn, None),
             cpal::SampleFormat::F64 => device.build_output_stream(&config, move|data: &mut [f64], _callback_info: &cpal::OutputCallbackInfo| cpal_thread(data, &stream_buffer), err_fn, None),
-            sf => panic!("Unsupported sample format {}", sf),
+            sf => { warn(format!("Unsupported sample format {}", sf)); return None; },
         }.unwrap();
 
         stream.play().unwrap();
@@ -496,14 +747,74 @@ impl AudioPlayer for NullAudioPlayer {
     }
 }
 
-fn run_test_mode() -> i32 {
-    let opt_cpu = Device::new_cgb();
+fn run_info(path: &str, json: bool) -> i32 {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => { warn(format!("Could not read {}: {}", path, e)); return EXITCODE_INFOFAILS; },
+    };
+    let header = match gbcart::CartridgeHeader::from_rom(&data) {
+        Some(header) => header,
+        None => { warn("Rom size to small"); return EXITCODE_INFOFAILS; },
+    };
+    if json {
+        println!("{}", header.to_json());
+    } else {
+        println!("{}", header);
+    }
+    EXITCODE_SUCCESS
+}
+
+fn run_save(matches: &clap::ArgMatches) -> i32 {
+    let (command, args) = matches.subcommand().unwrap();
+    let mut cartridge = FileCartridge::new(args.get_one::<String>("rom").unwrap());
+    if let Some(dir) = args.get_one::<String>("save-dir") {
+        cartridge = cartridge.with_save_dir(dir);
+    }
+    let file = std::path::Path::new(args.get_one::<String>("file").unwrap());
+    let result = match command {
+        "import" => mbc::import_save(&mut cartridge, file),
+        _ => mbc::export_save(&mut cartridge, file, !args.get_one::<bool>("no-rtc").copied().unwrap()),
+    };
+    match result {
+        Ok(report) => {
+            for note in &report.notes {
+                warn(format!("Note: {}", note));
+            }
+            match command {
+                "import" => println!("Imported {} to {}", file.display(), report.path.display()),
+                _ => println!("Exported {} to {}", report.path.display(), file.display()),
+            }
+            EXITCODE_SUCCESS
+        },
+        Err(message) => { warn(message); EXITCODE_SAVEFAILS },
+    }
+}
+
+fn attach_camera(cpu: &mut Device, camera: Option<&str>) -> Result<()> {
+    if let Some(spec) = camera {
+        cpu.set_camera_source(camera::open_source(spec)?);
+    }
+    Ok(())
+}
+
+fn run_test_mode(options: &CartridgeOptions, printer: bool, camera: Option<&str>) -> i32 {
+    let opt_cpu = load_device(options);
     let mut cpu = match opt_cpu {
         Err(errmsg) => { warn(errmsg); return EXITCODE_CPULOADFAILS; },
         Ok(cpu) => cpu,
     };
+    if let Err(errmsg) = attach_camera(&mut cpu, camera) {
+        warn(errmsg);
+        return EXITCODE_CPULOADFAILS;
+    }
 
-    cpu.set_stdout(true);
+    // The printer saves its pages as files, so a camera can be tested all the way to the print
+    if printer {
+        cpu.attach_printer();
+    }
+    else {
+        cpu.set_stdout(true);
+    }
     cpu.enable_audio(Box::new(NullAudioPlayer {}));
 
     // from masonforest, https://stackoverflow.com/a/55201400 (CC BY-SA 4.0)
@@ -526,7 +837,10 @@ fn run_test_mode() -> i32 {
             Err(TryRecvError::Disconnected) => break,
         }
         for _ in 0..1000 {
-            cpu.do_cycle();
+            if let Err(error) = cpu.do_cycle() {
+                warn(error);
+                return EXITCODE_GAMEFAILS;
+            }
         }
     }
     EXITCODE_SUCCESS
@@ -0,0 +1,118 @@
+//! Runs the emulated mappers behind the simulated cartridge adapter of gbcart, to check that the
+//! bank switching of the reader agrees with the emulator.
+
+use crate::mbc::MBC;
+use gbcart::simulator::CartridgeModel;
+
+/// Plugs an emulated cartridge into the simulated bus
+pub struct MbcModel(pub Box<dyn MBC>);
+
+impl CartridgeModel for MbcModel {
+    fn read_rom(&mut self, address: u16) -> u8 {
+        self.0.readrom(address)
+    }
+
+    fn read_ram(&mut self, address: u16) -> u8 {
+        self.0.readram(address)
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        self.0.writerom(address, value)
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        self.0.writeram(address, value)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MbcModel;
+    use crate::mbc::{get_mbc, HardwareMBC, MBC, MemoryCartridge};
+    use gbcart::simulator::{test_rom, SimulatedBus};
+    use gbcart::{Cartridge, RtcFooter, RTC_FOOTER_LEN};
+
+    const RETRIES: u32 = 2;
+
+    fn simulated_bus(rom: &[u8]) -> SimulatedBus<MbcModel> {
+        let mbc = get_mbc(&mut MemoryCartridge::new(rom.to_vec()), false).unwrap();
+        SimulatedBus::new(MbcModel(mbc))
+    }
+
+    #[test]
+    fn dumps_mbc5() {
+        let rom = test_rom(0x19, 0x03, 0x00);
+        let (dump, report) = Cartridge::new(simulated_bus(&rom)).read_rom(RETRIES);
+        assert!(dump == rom, "Dump differs from the simulated cartridge");
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn dumps_mbc1_hidden_banks() {
+        // 1 MiB, so banks 0x20 and 0x40 can only be read through the 0x0000 area
+        let rom = test_rom(0x01, 0x05, 0x00);
+        let (dump, _) = Cartridge::new(simulated_bus(&rom)).read_rom(RETRIES);
+        assert!(dump == rom, "MBC1 dump differs from the simulated cartridge");
+    }
+
+    #[test]
+    fn dumps_mbc2_and_mbc3() {
+        for cartridge_type in [0x06, 0x13] {
+            let rom = test_rom(cartridge_type, 0x03, 0x00);
+            let (dump, _) = Cartridge::new(simulated_bus(&rom)).read_rom(RETRIES);
+            assert!(dump == rom, "Dump of type {:02X} differs from the simulated cartridge", cartridge_type);
+        }
+    }
+
+    #[test]
+    fn saves_mbc3_clock_as_footer() {
+        let rom = test_rom(0x10, 0x03, 0x02);
+        let mut cartridge = Cartridge::new(simulated_bus(&rom));
+        let save = cartridge.read_ram().unwrap();
+        assert_eq!(save.len(), 0x2000 + RTC_FOOTER_LEN);
+        assert!(RtcFooter::split(&save, 0x2000).is_some());
+        cartridge.write_ram(&save).unwrap();
+        // Older saves with the clock in front of the RAM are still accepted
+        let mut legacy = vec![0; 8];
+        legacy.extend_from_slice(&save[..0x2000]);
+        cartridge.write_ram(&legacy).unwrap();
+    }
+
+    #[test]
+    fn saves_clock_of_rtc_only_carts() {
+        let rom = test_rom(0x0F, 0x03, 0x00);
+        let mut cartridge = Cartridge::new(simulated_bus(&rom));
+        let save = cartridge.read_ram().unwrap();
+        assert_eq!(save.len(), RTC_FOOTER_LEN);
+        assert!(RtcFooter::from_bytes(&save).is_some());
+        cartridge.write_ram(&save).unwrap();
+        assert!(cartridge.write_ram(&[0; 0x2000]).is_err());
+    }
+
+    #[test]
+    fn writes_reach_cartridge_ram() {
+        let rom = test_rom(0x1B, 0x01, 0x03);
+        let mut cartridge = Cartridge::new(simulated_bus(&rom));
+        cartridge.write_byte(0x0000, 0x0A);
+        cartridge.write_byte(0xA123, 0x5A);
+        assert_eq!(cartridge.read_byte(0xA123), 0x5A);
+        assert!(cartridge.pins().cartridge().0.dumpram()[0x123] == 0x5A);
+    }
+
+    #[test]
+    fn hardware_mbc_passes_through() {
+        let rom = test_rom(0x19, 0x02, 0x00);
+        let mut mbc = HardwareMBC::with_pins(Box::new(simulated_bus(&rom))).unwrap();
+        assert_eq!(mbc.romname(), "SIMULATE");
+        mbc.writerom(0x2000, 5);
+        assert_eq!(mbc.readrom(0x4321), rom[5 * 0x4000 + 0x0321]);
+    }
+
+    #[test]
+    fn hardware_mbc_requires_cartridge() {
+        let mut rom = test_rom(0x19, 0x02, 0x00);
+        rom[0x14D] ^= 0xFF;
+        let bus = SimulatedBus::new(MbcModel(get_mbc(&mut MemoryCartridge::new(rom), true).unwrap()));
+        assert!(HardwareMBC::with_pins(Box::new(bus)).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use crate::camera::{ImageSource, TestPattern, SENSOR_H, SENSOR_W};
+use crate::mbc::MBC;
+use crate::{Error, Result};
+use gbcart::{ram_banks, rom_banks};
+
+/// Number of camera registers, mirrored through 0xA000-0xBFFF
+const REGISTERS: usize = 0x36;
+/// Start of the dither matrix, 3 thresholds for each pixel of a 4x4 block
+const DITHER_MATRIX: usize = 0x06;
+/// Offset of the captured image in bank 0 of the RAM
+const IMAGE_OFFSET: usize = 0x0100;
+/// Exposure time at which the sensor output equals the input image
+const NEUTRAL_EXPOSURE: f32 = 0x0400 as f32;
+/// Edge enhancement ratios selected by bits 4-6 of register 4
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/// The Pocket Camera mapper with the Mitsubishi M64282FP sensor
+pub struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rambank: usize,
+    rombanks: usize,
+    rambanks: usize,
+    ram_on: bool,
+    ram_updated: bool,
+    /// Bank 0x10 maps the registers instead of the RAM
+    registers_selected: bool,
+    registers: [u8; REGISTERS],
+    source: Box<dyn ImageSource>,
+}
+
+impl PocketCamera {
+    pub fn new(data: Vec<u8>) -> Result<PocketCamera> {
+        let rombanks = rom_banks(data[0x148]);
+        let rambanks = ram_banks(data[0x149]);
+
+        let res = PocketCamera {
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            rombank: 1,
+            rambank: 0,
+            rombanks,
+            rambanks,
+            ram_on: false,
+            ram_updated: false,
+            registers_selected: false,
+            registers: [0; REGISTERS],
+            source: Box::new(TestPattern),
+        };
+
+        Ok(res)
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        if self.rambanks == 0 { return None }
+        Some(((self.rambank % self.rambanks) * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+
+    /// Takes a picture and stores it as 2 bit tiles, the same way the sensor and the mapper do
+    fn capture(&mut self) {
+        let input = self.source.capture();
+        let frame = self.process(&input);
+        let mut image = vec![0u8; SENSOR_W * SENSOR_H / 4];
+        for y in 0..SENSOR_H {
+            for x in 0..SENSOR_W {
+                let color = self.dither(x, y, frame[y * SENSOR_W + x]);
+                let tile = (y / 8) * (SENSOR_W / 8) + x / 8;
+                let offset = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                image[offset] |= (color & 1) << bit;
+                image[offset + 1] |= (color >> 1) << bit;
+            }
+        }
+        if let Some(ram) = self.ram.get_mut(IMAGE_OFFSET..IMAGE_OFFSET + image.len()) {
+            ram.copy_from_slice(&image);
+            self.ram_updated = true;
+        }
+    }
+
+    /// Applies the exposure, gain, inversion and edge enhancement of the sensor
+    fn process(&self, input: &[u8]) -> Vec<f32> {
+        let exposure = u16::from_be_bytes([self.registers[2], self.registers[3]]) as f32 / NEUTRAL_EXPOSURE;
+        // The gain runs from 14 dB to 45 dB in steps of 1 dB, relative to the lowest setting
+        let gain = 10f32.powf((self.registers[1] & 0x1F) as f32 / 20.0);
+        let invert = self.registers[4] & 0x08 != 0;
+        let pixels: Vec<f32> = input.iter().map(|&v| {
+            let v = v as f32 * exposure * gain;
+            if invert { 255.0 - v } else { v }
+        }).collect();
+
+        // N enables the enhancement, VH selects the directions it works in
+        let enhance = self.registers[1] & 0x80 != 0;
+        let (horizontal, vertical) = (self.registers[1] & 0x20 != 0, self.registers[1] & 0x40 != 0);
+        if !enhance || !(horizontal || vertical) {
+            return pixels;
+        }
+        let ratio = EDGE_RATIOS[((self.registers[4] >> 4) & 0x07) as usize];
+        let at = |x: usize, y: usize| pixels[y.min(SENSOR_H - 1) * SENSOR_W + x.min(SENSOR_W - 1)];
+        let mut output = Vec::with_capacity(pixels.len());
+        for y in 0..SENSOR_H {
+            for x in 0..SENSOR_W {
+                let center = at(x, y);
+                let mut edge = 0.0;
+                if horizontal {
+                    edge += 2.0 * center - at(x.saturating_sub(1), y) - at(x + 1, y);
+                }
+                if vertical {
+                    edge += 2.0 * center - at(x, y.saturating_sub(1)) - at(x, y + 1);
+                }
+                output.push(center + edge * ratio / 2.0);
+            }
+        }
+        output
+    }
+
+    /// Maps a pixel to one of the 4 colours with the thresholds of the dither matrix
+    fn dither(&self, x: usize, y: usize, value: f32) -> u8 {
+        let index = DITHER_MATRIX + ((y % 4) * 4 + x % 4) * 3;
+        let thresholds = &self.registers[index..index + 3];
+        match value {
+            v if v < thresholds[0] as f32 => 3,
+            v if v < thresholds[1] as f32 => 2,
+            v if v < thresholds[2] as f32 => 1,
+            _ => 0,
+        }
+    }
+}
+
+impl MBC for PocketCamera {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if self.registers_selected {
+            // Only the capture flags can be read back, the capture finishes right away
+            return if a & 0x7F == 0 { self.registers[0] & 0x06 } else { 0x00 };
+        }
+        self.ram_address(a).map_or(0xFF, |address| self.ram[address])
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x1FFF => self.ram_on = v & 0x0F == 0x0A,
+            0x2000 ..= 0x3FFF => self.rombank = (v & 0x3F) as usize % self.rombanks,
+            0x4000 ..= 0x5FFF => {
+                self.registers_selected = v & 0x10 != 0;
+                self.rambank = (v & 0x0F) as usize;
+            },
+            0x6000 ..= 0x7FFF => {},
+            _ => {},
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if self.registers_selected {
+            let register = (a & 0x7F) as usize;
+            if register < REGISTERS {
+                self.registers[register] = v;
+            }
+            if register == 0 && v & 0x01 != 0 {
+                self.capture();
+            }
+            return;
+        }
+        if !self.ram_on { return }
+        if let Some(address) = self.ram_address(a) {
+            self.ram[address] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err(Error::Save("Loaded RAM has incorrect length"));
+        }
+
+        self.ram = ramdata.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = source;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::PocketCamera;
+    use crate::camera::{StillImage, SENSOR_H, SENSOR_W};
+    use crate::mbc::MBC;
+
+    fn camera(frame: Vec<u8>) -> PocketCamera {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = 0xFC;
+        data[0x149] = 0x04;
+        let mut mbc = PocketCamera::new(data).unwrap();
+        mbc.set_image_source(Box::new(StillImage::new(frame).unwrap()));
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x10);
+        // Neutral exposure, and thresholds at 64, 128 and 192 everywhere
+        mbc.writeram(0xA002, 0x04);
+        mbc.writeram(0xA003, 0x00);
+        for i in 0..16 {
+            for (j, threshold) in [64, 128, 192].into_iter().enumerate() {
+                mbc.writeram(0xA006 + i * 3 + j as u16, threshold);
+            }
+        }
+        mbc
+    }
+
+    #[test]
+    fn captures_dithered_tiles() {
+        // Four vertical stripes from black to white, 32 pixels each
+        let frame: Vec<u8> = (0..SENSOR_W * SENSOR_H).map(|i| [0, 100, 150, 255][(i % SENSOR_W) / 32]).collect();
+        let mut mbc = camera(frame);
+        mbc.writeram(0xA000, 0x01);
+        assert_eq!(mbc.readram(0xA000) & 0x01, 0);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        mbc.writerom(0x4000, 0x00);
+        let tile_row = |tile: u16| (mbc.readram(0xA100 + tile * 16), mbc.readram(0xA101 + tile * 16));
+        // Black is colour 3, then 2, 1 and white is 0
+        assert_eq!(tile_row(0), (0xFF, 0xFF));
+        assert_eq!(tile_row(4), (0x00, 0xFF));
+        assert_eq!(tile_row(8), (0xFF, 0x00));
+        assert_eq!(tile_row(12), (0x00, 0x00));
+        assert_eq!(tile_row(13 * 16), (0xFF, 0xFF));
+    }
+
+    #[test]
+    fn inverts_image() {
+        let mut mbc = camera(vec![0; SENSOR_W * SENSOR_H]);
+        mbc.writeram(0xA004, 0x08);
+        mbc.writeram(0xA000, 0x01);
+        mbc.writerom(0x4000, 0x00);
+        assert!((0xA100..0xAF00).all(|a| mbc.readram(a) == 0x00));
+    }
+}
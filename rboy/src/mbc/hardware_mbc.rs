@@ -1,61 +1,70 @@
-use crate::mbc::{mbc0, mbc1, mbc2, mbc3, mbc5, MBC};
-use crate::mbc::cartridge_reader::CartridgeReader;
-use crate::StrResult;
+use crate::mbc::MBC;
+use crate::{Error, Result};
+use gbcart::{BusConfig, BusTiming, Cartridge, CartridgePins};
+use std::cell::RefCell;
 
+/// Runs the game directly from the physical cartridge.
+///
+/// Every ROM and RAM access is forwarded over the GPIO bus, so the mapper, SRAM and RTC of the
+/// cartridge itself do all the work. Saves stay on the cartridge, so nothing is written to disk.
+/// Each access shifts a full address out to the adapter, which makes this a lot slower than
+/// running a dumped ROM.
 pub struct HardwareMBC {
-    mbc: Box<dyn MBC>,
+    bus: RefCell<Cartridge<Box<dyn CartridgePins + Send>>>,
 }
 
 impl HardwareMBC {
-    pub fn new() -> StrResult<HardwareMBC> {
-        Ok(HardwareMBC {
-            mbc: HardwareMBC::get_mbc(),
-        })
+    pub fn new(bus: &BusConfig) -> Result<HardwareMBC> {
+        let cartridge = Cartridge::open_with(bus)?;
+        if bus.timing == BusTiming::Calibrate {
+            println!("Calibrated bus delay: {} ns", cartridge.delay().as_nanos());
+        }
+        HardwareMBC::with_pins(Box::new(cartridge.into_pins()))
     }
 
-    fn get_mbc() -> Box<dyn MBC+'static> {
-        let mut cartridge_reader = CartridgeReader::new();
-        match cartridge_reader.read_byte(0x147) {
-            0x00 => Box::new(mbc0::MBC0::new(cartridge_reader)) as Box<dyn MBC>,
-            0x01 ..= 0x03 => Box::new(mbc1::MBC1::new(cartridge_reader)) as Box<dyn MBC>,
-            0x05 ..= 0x06 => Box::new(mbc2::MBC2::new(cartridge_reader)) as Box<dyn MBC>,
-            0x0F ..= 0x13 => Box::new(mbc3::MBC3::new(cartridge_reader)) as Box<dyn MBC>,
-            0x19 ..= 0x1E => Box::new(mbc5::MBC5::new(cartridge_reader)) as Box<dyn MBC>,
-            _ => { panic!("Unsupported MBC type") },
+    pub fn with_pins(pins: Box<dyn CartridgePins + Send>) -> Result<HardwareMBC> {
+        let mut cartridge = Cartridge::new(pins);
+        if !cartridge.read_header().checksum_ok() {
+            return Err(Error::Cartridge("No cartridge detected or the cartridge header is corrupt"));
         }
+
+        Ok(HardwareMBC {
+            bus: RefCell::new(cartridge),
+        })
     }
 }
 
 impl MBC for HardwareMBC {
-    fn readrom(&mut self, a: u16) -> u8 {
-        self.mbc.readrom(a)
+    fn readrom(&self, a: u16) -> u8 {
+        self.bus.borrow_mut().read_byte(a)
     }
 
-    fn readram(&mut self, a: u16) -> u8 {
-        self.mbc.readram(a)
+    fn readram(&self, a: u16) -> u8 {
+        self.bus.borrow_mut().read_byte(a)
     }
 
     fn writerom(&mut self, a: u16, v: u8) {
-        self.mbc.writerom(a, v)
+        self.bus.get_mut().write_byte(a, v)
     }
 
     fn writeram(&mut self, a: u16, v: u8) {
-        self.mbc.writeram(a, v)
+        self.bus.get_mut().write_byte(a, v)
     }
 
     fn check_and_reset_ram_updated(&mut self) -> bool {
-        self.mbc.check_and_reset_ram_updated()
+        false
     }
 
     fn is_battery_backed(&self) -> bool {
-        self.mbc.is_battery_backed()
+        // The battery on the cartridge keeps the RAM, there is nothing to persist here
+        false
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
-        self.mbc.loadram(ramdata)
+    fn loadram(&mut self, _ramdata: &[u8]) -> Result<()> {
+        Err(Error::Save("The RAM of a live cartridge can not be replaced"))
     }
 
     fn dumpram(&self) -> Vec<u8> {
-        self.mbc.dumpram()
+        Vec::new()
     }
 }
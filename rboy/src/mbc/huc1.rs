@@ -0,0 +1,164 @@
+use crate::mbc::MBC;
+use crate::{Error, Result};
+use gbcart::{ram_banks, rom_banks};
+
+/// Value read from the infrared port while no light is received
+pub const IR_NO_LIGHT: u8 = 0xC0;
+
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rambank: usize,
+    rombanks: usize,
+    rambanks: usize,
+    /// 0xA000-0xBFFF accesses the infrared port instead of the RAM
+    ir_mode: bool,
+    ram_updated: bool,
+}
+
+impl HuC1 {
+    pub fn new(data: Vec<u8>) -> Result<HuC1> {
+        let rambanks = ram_banks(data[0x149]);
+        let rombanks = rom_banks(data[0x148]);
+
+        let res = HuC1 {
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            rombank: 1,
+            rambank: 0,
+            rombanks,
+            rambanks,
+            ir_mode: false,
+            ram_updated: false,
+        };
+
+        Ok(res)
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        if self.rambanks == 0 { return None }
+        Some(((self.rambank % self.rambanks) * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+}
+
+impl MBC for HuC1 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if self.ir_mode { return IR_NO_LIGHT }
+        self.ram_address(a).map_or(0xFF, |address| self.ram[address])
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x1FFF => self.ir_mode = v & 0x0F == 0x0E,
+            0x2000 ..= 0x3FFF => {
+                self.rombank = match (v & 0x3F) as usize % self.rombanks { 0 => 1, n => n };
+            },
+            0x4000 ..= 0x5FFF => self.rambank = (v & 0x03) as usize,
+            0x6000 ..= 0x7FFF => {},
+            _ => {},
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        // There is no other Game Boy to receive the infrared LED
+        if self.ir_mode { return }
+        if let Some(address) = self.ram_address(a) {
+            self.ram[address] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err(Error::Save("Loaded RAM has incorrect length"));
+        }
+
+        self.ram = ramdata.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HuC1, IR_NO_LIGHT};
+    use crate::mbc::MBC;
+
+    /// A 1 MiB HuC1 ROM with 32 KiB of RAM, whose banks start with their own number
+    fn cartridge() -> HuC1 {
+        let mut data = vec![0; 0x40 * 0x4000];
+        for bank in 0..0x40 {
+            data[bank * 0x4000] = bank as u8;
+        }
+        data[0x147] = 0xFF;
+        data[0x148] = 0x05;
+        data[0x149] = 0x03;
+        HuC1::new(data).unwrap()
+    }
+
+    #[test]
+    fn switches_banks() {
+        let mut mbc = cartridge();
+        assert_eq!(mbc.readrom(0x4000), 0x01);
+        mbc.writerom(0x2000, 0x25);
+        assert_eq!((mbc.readrom(0x0000), mbc.readrom(0x4000)), (0x00, 0x25));
+        // Bank 0 can not be mapped at 0x4000
+        mbc.writerom(0x2000, 0x00);
+        assert_eq!(mbc.readrom(0x4000), 0x01);
+
+        for bank in 0..4 {
+            mbc.writerom(0x4000, bank);
+            mbc.writeram(0xA123, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            mbc.writerom(0x4000, bank);
+            assert_eq!(mbc.readram(0xA123), 0x10 + bank);
+        }
+        assert!(mbc.check_and_reset_ram_updated());
+    }
+
+    #[test]
+    fn ir_mode_hides_the_ram() {
+        let mut mbc = cartridge();
+        mbc.writeram(0xA000, 0x42);
+        mbc.writerom(0x0000, 0x0E);
+        assert_eq!(mbc.readram(0xA000), IR_NO_LIGHT);
+        // Writes go to the infrared LED, so the RAM keeps its value
+        mbc.writeram(0xA000, 0x01);
+        mbc.writerom(0x0000, 0x00);
+        assert_eq!(mbc.readram(0xA000), 0x42);
+        mbc.writerom(0x0000, 0x0A);
+        assert_eq!(mbc.readram(0xA000), 0x42);
+    }
+
+    #[test]
+    fn ram_without_ram_chip_reads_open_bus() {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = 0xFF;
+        let mut mbc = HuC1::new(data).unwrap();
+        mbc.writeram(0xA000, 0x42);
+        assert_eq!(mbc.readram(0xA000), 0xFF);
+        assert!(!mbc.check_and_reset_ram_updated());
+    }
+}
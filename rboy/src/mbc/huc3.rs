@@ -0,0 +1,280 @@
+use crate::mbc::huc1::IR_NO_LIGHT;
+use crate::mbc::MBC;
+use crate::rtc::now;
+use crate::{Error, Result};
+use gbcart::{ram_banks, rom_banks};
+
+use std::convert::TryInto;
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+/// Nibbles of RTC memory, which also hold the alarm and event data of the game
+const RTC_MEMORY_SIZE: usize = 0x100;
+/// The clock and its memory in front of the RAM in a save
+pub const SAVE_PREFIX: usize = 8 + RTC_MEMORY_SIZE;
+
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rambank: usize,
+    rombanks: usize,
+    rambanks: usize,
+    /// What 0xA000-0xBFFF accesses, selected by writes to 0x0000-0x1FFF
+    mode: u8,
+    ram_updated: bool,
+    rtc: Rtc,
+}
+
+impl HuC3 {
+    pub fn new(data: Vec<u8>) -> Result<HuC3> {
+        let rambanks = ram_banks(data[0x149]);
+        let rombanks = rom_banks(data[0x148]);
+
+        let res = HuC3 {
+            rom: data,
+            ram: vec![0; rambanks * 0x2000],
+            rombank: 1,
+            rambank: 0,
+            rombanks,
+            rambanks,
+            mode: 0,
+            ram_updated: false,
+            rtc: Rtc::new(),
+        };
+
+        Ok(res)
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        if self.rambanks == 0 { return None }
+        Some(((self.rambank % self.rambanks) * 0x2000) | ((a as usize) & 0x1FFF))
+    }
+}
+
+impl MBC for HuC3 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        match self.mode {
+            0x0 | 0xA => self.ram_address(a).map_or(0xFF, |address| self.ram[address]),
+            0xC => self.rtc.response(),
+            // The RTC is always done with the command by the time the game checks
+            0xD => 0xFF,
+            0xE => IR_NO_LIGHT,
+            _ => 0xFF,
+        }
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x1FFF => self.mode = v & 0x0F,
+            0x2000 ..= 0x3FFF => self.rombank = (v & 0x7F) as usize % self.rombanks,
+            0x4000 ..= 0x5FFF => self.rambank = (v & 0x03) as usize,
+            0x6000 ..= 0x7FFF => {},
+            _ => {},
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        match self.mode {
+            0xA => if let Some(address) = self.ram_address(a) {
+                self.ram[address] = v;
+                self.ram_updated = true;
+            },
+            0xB if self.rtc.command(v) => self.ram_updated = true,
+            _ => {},
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        // Older saves have only the clock in front of the RAM, without the memory
+        if !matches!(ramdata.len().checked_sub(self.ram.len()), Some(8) | Some(SAVE_PREFIX)) {
+            return Err(Error::Save("Loaded ram is too small"));
+        }
+
+        let (int_bytes, rest) = ramdata.split_at(8);
+        self.rtc.zero = u64::from_be_bytes(int_bytes.try_into().unwrap());
+        let (nibbles, ram) = rest.split_at(rest.len() - self.ram.len());
+        self.rtc.memory = [0; RTC_MEMORY_SIZE];
+        self.rtc.memory[..nibbles.len()].copy_from_slice(nibbles);
+        self.ram = ram.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let mut file = self.rtc.zero.to_be_bytes().to_vec();
+        file.extend_from_slice(&self.rtc.memory);
+        file.extend_from_slice(&self.ram);
+        file
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+}
+
+/// The clock of the HuC3, which counts minutes and days. The game talks to it through commands
+/// which read and write a memory of 4 bit values.
+struct Rtc {
+    /// Unix time at which the clock was at day 0, minute 0
+    zero: u64,
+    memory: [u8; RTC_MEMORY_SIZE],
+    address: u8,
+    /// The last command, with its result in the lower 4 bits
+    response: u8,
+}
+
+impl Rtc {
+    fn new() -> Rtc {
+        Rtc { zero: now(), memory: [0; RTC_MEMORY_SIZE], address: 0, response: 0 }
+    }
+
+    fn response(&self) -> u8 {
+        0x80 | self.response
+    }
+
+    /// Runs a command, returns whether the time or the memory, which are both saved, changed
+    fn command(&mut self, v: u8) -> bool {
+        let command = (v >> 4) & 0x07;
+        let argument = v & 0x0F;
+        let mut result = argument;
+        let mut changed = false;
+        match command {
+            // Read and increment
+            0x1 => {
+                result = self.memory[self.address as usize];
+                self.address = self.address.wrapping_add(1);
+            },
+            // Write and increment
+            0x3 => {
+                self.memory[self.address as usize] = argument;
+                self.address = self.address.wrapping_add(1);
+                changed = true;
+            },
+            0x4 => self.address = (self.address & 0xF0) | argument,
+            0x5 => self.address = (self.address & 0x0F) | (argument << 4),
+            0x6 => match argument {
+                0x0 => self.copy_time_to_memory(),
+                0x1 => {
+                    self.copy_memory_to_time();
+                    changed = true;
+                },
+                // Status, the clock is always running fine
+                0x2 => result = 0x1,
+                // 0xE plays a tone on the speaker of the cartridge, which is not emulated
+                _ => {},
+            },
+            _ => {},
+        }
+        self.response = command << 4 | result;
+        changed
+    }
+
+    /// Current minute of the day and day counter
+    fn time(&self) -> (u64, u64) {
+        let minutes = now().saturating_sub(self.zero) / 60;
+        (minutes % MINUTES_PER_DAY, (minutes / MINUTES_PER_DAY) & 0xFFF)
+    }
+
+    /// Latches the time at 0x00-0x02 (minutes) and 0x03-0x05 (days), least significant nibble first
+    fn copy_time_to_memory(&mut self) {
+        let (minutes, days) = self.time();
+        let value = days << 12 | minutes;
+        for (i, nibble) in self.memory[..6].iter_mut().enumerate() {
+            *nibble = ((value >> (i * 4)) & 0x0F) as u8;
+        }
+    }
+
+    fn copy_memory_to_time(&mut self) {
+        let value = self.memory[..6].iter().rev().fold(0u64, |value, &nibble| value << 4 | nibble as u64);
+        let (minutes, days) = (value & 0xFFF, value >> 12);
+        self.zero = now().saturating_sub((days * MINUTES_PER_DAY + minutes) * 60);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HuC3, SAVE_PREFIX};
+    use crate::mbc::MBC;
+
+    fn rtc_command(mbc: &mut HuC3, command: u8, argument: u8) -> u8 {
+        mbc.writerom(0x0000, 0x0B);
+        mbc.writeram(0xA000, command << 4 | argument);
+        mbc.writerom(0x0000, 0x0C);
+        mbc.readram(0xA000) & 0x0F
+    }
+
+    fn cartridge() -> HuC3 {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = 0xFE;
+        data[0x149] = 0x02;
+        HuC3::new(data).unwrap()
+    }
+
+    #[test]
+    fn sets_and_reads_clock() {
+        let mut mbc = cartridge();
+
+        // Day 0x123, minute 0x2AB, written from address 0
+        rtc_command(&mut mbc, 0x4, 0x0);
+        rtc_command(&mut mbc, 0x5, 0x0);
+        for nibble in [0xB, 0xA, 0x2, 0x3, 0x2, 0x1] {
+            rtc_command(&mut mbc, 0x3, nibble);
+        }
+        rtc_command(&mut mbc, 0x6, 0x1);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        // Persisted with the RAM, a reload keeps the time
+        let mut reloaded = HuC3::new(mbc.rom.clone()).unwrap();
+        reloaded.loadram(&mbc.dumpram()).unwrap();
+        rtc_command(&mut reloaded, 0x6, 0x0);
+        rtc_command(&mut reloaded, 0x4, 0x0);
+        let nibbles: Vec<u8> = (0..6).map(|_| rtc_command(&mut reloaded, 0x1, 0x0)).collect();
+        // The minute may have ticked over while the test ran
+        assert!(nibbles[..2] == [0xB, 0xA] || nibbles[..2] == [0xC, 0xA]);
+        assert_eq!(nibbles[2..], [0x2, 0x3, 0x2, 0x1]);
+
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writeram(0xA000, 0x42);
+        mbc.writerom(0x0000, 0x00);
+        assert_eq!(mbc.readram(0xA000), 0x42);
+    }
+
+    #[test]
+    fn saves_rtc_memory() {
+        let mut mbc = cartridge();
+        // An alarm, written to the memory above the time
+        rtc_command(&mut mbc, 0x4, 0x8);
+        rtc_command(&mut mbc, 0x5, 0x1);
+        for nibble in [0x7, 0x0, 0x3] {
+            rtc_command(&mut mbc, 0x3, nibble);
+        }
+        assert!(mbc.check_and_reset_ram_updated());
+
+        let save = mbc.dumpram();
+        assert_eq!(save.len(), SAVE_PREFIX + 0x2000);
+        let mut reloaded = HuC3::new(mbc.rom.clone()).unwrap();
+        reloaded.loadram(&save).unwrap();
+        rtc_command(&mut reloaded, 0x4, 0x8);
+        rtc_command(&mut reloaded, 0x5, 0x1);
+        let nibbles: Vec<u8> = (0..3).map(|_| rtc_command(&mut reloaded, 0x1, 0x0)).collect();
+        assert_eq!(nibbles, [0x7, 0x0, 0x3]);
+        assert_eq!(reloaded.dumpram(), save);
+
+        // Saves from before the memory was kept still load
+        let mut old = save[..8].to_vec();
+        old.extend_from_slice(&save[SAVE_PREFIX..]);
+        reloaded.loadram(&old).unwrap();
+        assert_eq!(reloaded.dumpram()[8..SAVE_PREFIX], [0; 0x100]);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::StrResult;
+use crate::Result;
 use crate::mbc::MBC;
 
 pub struct MBC0 {
@@ -6,7 +6,7 @@ pub struct MBC0 {
 }
 
 impl MBC0 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC0> {
+    pub fn new(data: Vec<u8>) -> Result<MBC0> {
         Ok(MBC0 { rom: data })
     }
 }
@@ -18,7 +18,7 @@ impl MBC for MBC0 {
     fn writeram(&mut self, _a: u16, _v: u8) { () }
 
     fn is_battery_backed(&self) -> bool { false }
-    fn loadram(&mut self, _ramdata: &[u8]) -> StrResult<()> { Ok(()) }
+    fn loadram(&mut self, _ramdata: &[u8]) -> Result<()> { Ok(()) }
     fn dumpram(&self) -> Vec<u8> { Vec::new() }
     fn check_and_reset_ram_updated(&mut self) -> bool { false }
 }
@@ -1,5 +1,9 @@
-use crate::mbc::{MBC, ram_banks, rom_banks};
-use crate::StrResult;
+use crate::mbc::MBC;
+use crate::{Error, Result};
+use gbcart::{ram_banks, rom_banks, CartridgeHeader};
+
+/// Size of the games in a multicart, which each start with their own header
+const MULTICART_GAME_BANKS: usize = 0x10;
 
 pub struct MBC1 {
     rom: Vec<u8>,
@@ -7,15 +11,19 @@ pub struct MBC1 {
     ram_on: bool,
     ram_updated:bool,
     banking_mode: u8,
-    rombank: usize,
-    rambank: usize,
+    /// The 5 bit register at 0x2000-0x3FFF, never 0
+    bank1: usize,
+    /// The 2 bit register at 0x4000-0x5FFF
+    bank2: usize,
     has_battery: bool,
     rombanks: usize,
     rambanks: usize,
+    /// MBC1M multicarts connect the upper bank bits to bit 4 instead of bit 5
+    multicart: bool,
 }
 
 impl MBC1 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC1> {
+    pub fn new(data: Vec<u8>) -> Result<MBC1> {
         let (has_battery, rambanks) = match data[0x147] {
             0x02 => (false, ram_banks(data[0x149])),
             0x03 => (true, ram_banks(data[0x149])),
@@ -23,22 +31,44 @@ impl MBC1 {
         };
         let rombanks = rom_banks(data[0x148]);
         let ramsize = rambanks * 0x2000;
+        let multicart = is_multicart(&data);
 
         let res = MBC1 {
             rom: data,
             ram: ::std::iter::repeat(0u8).take(ramsize).collect(),
             ram_on: false,
             banking_mode: 0,
-            rombank: 1,
-            rambank: 0,
+            bank1: 1,
+            bank2: 0,
             ram_updated: false,
             has_battery: has_battery,
             rombanks: rombanks,
             rambanks: rambanks,
+            multicart,
         };
 
         Ok(res)
     }
+
+    fn upper_bank_shift(&self) -> usize {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn rombank(&self) -> usize {
+        let shift = self.upper_bank_shift();
+        ((self.bank2 << shift) | (self.bank1 & ((1 << shift) - 1))) % self.rombanks
+    }
+
+    fn rambank(&self) -> usize {
+        if self.banking_mode == 1 && self.rambanks > 1 { self.bank2 } else { 0 }
+    }
+}
+
+/// Multicarts are 8 Mbit carts where the second game, at bank 0x10, also has a valid logo
+fn is_multicart(data: &[u8]) -> bool {
+    let offset = MULTICART_GAME_BANKS * 0x4000;
+    data.len() == 0x40 * 0x4000
+        && CartridgeHeader::from_bytes(&data[offset..]).is_some_and(|header| header.logo_ok)
 }
 
 impl MBC for MBC1 {
@@ -48,49 +78,40 @@ impl MBC for MBC1 {
                 0
             }
             else {
-                self.rombank & 0xE0
+                (self.bank2 << self.upper_bank_shift()) % self.rombanks
             }
         }
         else {
-            self.rombank
+            self.rombank()
         };
         let idx = bank * 0x4000 | ((a as usize) & 0x3FFF);
         *self.rom.get(idx).unwrap_or(&0xFF)
     }
     fn readram(&self, a: u16) -> u8 {
         if !self.ram_on { return 0xFF }
-        let rambank = if self.banking_mode == 1 { self.rambank } else { 0 };
-        self.ram[(rambank * 0x2000) | ((a & 0x1FFF) as usize)]
+        let address = (self.rambank() * 0x2000) | ((a & 0x1FFF) as usize);
+        *self.ram.get(address).unwrap_or(&0xFF)
     }
 
     fn writerom(&mut self, a: u16, v: u8) {
         match a {
             0x0000 ..= 0x1FFF => { self.ram_on = v & 0xF == 0xA; },
             0x2000 ..= 0x3FFF => {
-                let lower_bits = match (v as usize) & 0x1F {
+                // The zero check sees all 5 bits, even when a multicart ignores bit 4
+                self.bank1 = match (v as usize) & 0x1F {
                     0 => 1,
                     n => n,
                 };
-                self.rombank = ((self.rombank & 0x60) | lower_bits) % self.rombanks;
-            },
-            0x4000 ..= 0x5FFF => {
-                if self.rombanks > 0x20 {
-                    let upper_bits = (v as usize & 0x03) % (self.rombanks >> 5);
-                    self.rombank = self.rombank & 0x1F | (upper_bits << 5)
-                }
-                if self.rambanks > 1 {
-                    self.rambank = (v as usize) & 0x03;
-                }
             },
+            0x4000 ..= 0x5FFF => { self.bank2 = (v as usize) & 0x03; },
             0x6000 ..= 0x7FFF => { self.banking_mode = v & 0x01; },
-            _ => panic!("Could not write to {:04X} (MBC1)", a),
+            _ => {},
         }
     }
 
     fn writeram(&mut self, a: u16, v: u8) {
         if !self.ram_on { return }
-        let rambank = if self.banking_mode == 1 { self.rambank } else { 0 };
-        let address = (rambank * 0x2000) | ((a & 0x1FFF) as usize);
+        let address = (self.rambank() * 0x2000) | ((a & 0x1FFF) as usize);
         if address < self.ram.len() {
             self.ram[address] = v;
             self.ram_updated = true;
@@ -101,9 +122,9 @@ impl MBC for MBC1 {
         self.has_battery
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         if ramdata.len() != self.ram.len() {
-            return Err("Loaded RAM has incorrect length");
+            return Err(Error::Save("Loaded RAM has incorrect length"));
         }
 
         self.ram = ramdata.to_vec();
@@ -121,3 +142,64 @@ impl MBC for MBC1 {
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::MBC1;
+    use crate::mbc::MBC;
+    use gbcart::NINTENDO_LOGO;
+
+    /// An 8 Mbit MBC1 ROM whose banks start with their own number
+    fn rom(multicart: bool) -> Vec<u8> {
+        let mut data = vec![0; 0x40 * 0x4000];
+        for bank in 0..0x40 {
+            data[bank * 0x4000] = bank as u8;
+        }
+        data[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        if multicart {
+            data[0x40104..0x40134].copy_from_slice(&NINTENDO_LOGO);
+        }
+        data[0x147] = 0x01;
+        data[0x148] = 0x05;
+        data
+    }
+
+    fn select(mbc: &mut MBC1, lower: u8, upper: u8) -> (u8, u8) {
+        mbc.writerom(0x2000, lower);
+        mbc.writerom(0x4000, upper);
+        (mbc.readrom(0x0000), mbc.readrom(0x4000))
+    }
+
+    #[test]
+    fn standard_banking() {
+        let mut mbc = MBC1::new(rom(false)).unwrap();
+        assert!(!mbc.multicart);
+        assert_eq!(select(&mut mbc, 0x12, 1), (0x00, 0x32));
+        assert_eq!(select(&mut mbc, 0x00, 0), (0x00, 0x01));
+        mbc.writerom(0x6000, 1);
+        assert_eq!(select(&mut mbc, 0x05, 1), (0x20, 0x25));
+    }
+
+    #[test]
+    fn multicart_banking() {
+        let mut mbc = MBC1::new(rom(true)).unwrap();
+        assert!(mbc.multicart);
+        assert_eq!(select(&mut mbc, 0x12, 1), (0x00, 0x12));
+        // Bit 4 is not connected, but still counts for the zero check
+        assert_eq!(select(&mut mbc, 0x10, 0), (0x00, 0x00));
+        assert_eq!(select(&mut mbc, 0x00, 2), (0x00, 0x21));
+        mbc.writerom(0x6000, 1);
+        assert_eq!(select(&mut mbc, 0x03, 3), (0x30, 0x33));
+    }
+
+    #[test]
+    fn missing_ram_reads_open_bus() {
+        let mut mbc = MBC1::new(rom(false)).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writeram(0xA000, 0x12);
+        assert_eq!(mbc.readram(0xA000), 0xFF);
+        mbc.writerom(0x6000, 1);
+        mbc.writerom(0x4000, 3);
+        assert_eq!(mbc.readram(0xBFFF), 0xFF);
+    }
+}
@@ -1,5 +1,6 @@
-use crate::mbc::{MBC, rom_banks};
-use crate::StrResult;
+use crate::mbc::MBC;
+use crate::{Error, Result};
+use gbcart::rom_banks;
 
 pub struct MBC2 {
     rom: Vec<u8>,
@@ -12,7 +13,7 @@ pub struct MBC2 {
 }
 
 impl MBC2 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC2> {
+    pub fn new(data: Vec<u8>) -> Result<MBC2> {
         let has_battery = match data[0x147] {
             0x06 => true,
             _ => false,
@@ -76,9 +77,9 @@ impl MBC for MBC2 {
         self.has_battery
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         if ramdata.len() != self.ram.len() {
-            return Err("Loaded RAM has incorrect length");
+            return Err(Error::Save("Loaded RAM has incorrect length"));
         }
 
         self.ram = ramdata.to_vec();
@@ -1,8 +1,8 @@
-use crate::mbc::{MBC, ram_banks};
-use crate::StrResult;
+use crate::mbc::MBC;
+use crate::rtc::{Rtc, RtcMode};
+use crate::{Error, Result};
+use gbcart::{ram_banks, rom_banks, RtcFooter};
 
-use std::io::prelude::*;
-use std::time;
 use std::convert::TryInto;
 
 pub struct MBC3 {
@@ -15,13 +15,13 @@ pub struct MBC3 {
     ram_on: bool,
     ram_updated: bool,
     has_battery: bool,
-    rtc_ram: [u8; 5],
-    rtc_ram_latch: [u8; 5],
-    rtc_zero: Option<u64>,
+    rtc: Option<Rtc>,
+    /// MBC30 has 8 bits of ROM bank and 3 bits of RAM bank, for up to 4 MiB of ROM and 64 KiB of RAM
+    mbc30: bool,
 }
 
 impl MBC3 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC3> {
+    pub fn new(data: Vec<u8>) -> Result<MBC3> {
         let subtype = data[0x147];
         let has_battery = match subtype {
             0x0F | 0x10 | 0x13 => true,
@@ -32,8 +32,10 @@ impl MBC3 {
             _ => 0,
         };
         let ramsize = rambanks * 0x2000;
+        // MBC30 uses the same cartridge types, only its sizes tell it apart
+        let mbc30 = rom_banks(data[0x148]) > 128 || rambanks > 4;
         let rtc = match subtype {
-            0x0F | 0x10 => Some(0),
+            0x0F | 0x10 => Some(Rtc::new()),
             _ => None,
         };
 
@@ -47,66 +49,12 @@ impl MBC3 {
             ram_on: false,
             ram_updated: false,
             has_battery: has_battery,
-            rtc_ram: [0u8; 5],
-            rtc_ram_latch: [0u8; 5],
-            rtc_zero: rtc,
+            rtc,
+            mbc30,
         };
 
         Ok(res)
     }
-
-    fn latch_rtc_reg(&mut self) {
-        self.calc_rtc_reg();
-        self.rtc_ram_latch.clone_from_slice(&self.rtc_ram);
-    }
-
-    fn calc_rtc_reg(&mut self) {
-        // Do not modify regs when halted
-        if self.rtc_ram[4] & 0x40 == 0x40 { return }
-
-        let tzero = match self.rtc_zero {
-            Some(t) => time::UNIX_EPOCH + time::Duration::from_secs(t),
-            None => return,
-        };
-
-        if self.compute_difftime() == self.rtc_zero {
-            // No time has passed. Do not alter registers
-            return;
-        }
-
-        let difftime = match time::SystemTime::now().duration_since(tzero) {
-            Ok(n) => { n.as_secs() },
-            _ => { 0 },
-        };
-        self.rtc_ram[0] = (difftime % 60) as u8;
-        self.rtc_ram[1] = ((difftime / 60) % 60) as u8;
-        self.rtc_ram[2] = ((difftime / 3600) % 24) as u8;
-        let days = difftime / (3600*24);
-        self.rtc_ram[3] = days as u8;
-        self.rtc_ram[4] = (self.rtc_ram[4] & 0xFE) | (((days >> 8) & 0x01) as u8);
-        if days >= 512 {
-            self.rtc_ram[4] |= 0x80;
-            self.calc_rtc_zero();
-        }
-    }
-
-    fn compute_difftime(&self) -> Option<u64> {
-        if self.rtc_zero.is_none() { return None; }
-        let mut difftime = match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
-            Ok(t) => t.as_secs(),
-            Err(_) => panic!("System clock is set to a time before the unix epoch (1970-01-01)"),
-        };
-        difftime -= self.rtc_ram[0] as u64;
-        difftime -= (self.rtc_ram[1] as u64) * 60;
-        difftime -= (self.rtc_ram[2] as u64) * 3600;
-        let days = ((self.rtc_ram[4] as u64 & 0x1) << 8) | (self.rtc_ram[3] as u64);
-        difftime -= days * 3600 * 24;
-        Some(difftime)
-    }
-
-    fn calc_rtc_zero(&mut self) {
-        self.rtc_zero = self.compute_difftime();
-    }
 }
 
 impl MBC for MBC3 {
@@ -119,8 +67,8 @@ impl MBC for MBC3 {
         if !self.ram_on { return 0xFF }
         if !self.selectrtc && self.rambank < self.rambanks {
             self.ram[self.rambank * 0x2000 | ((a as usize) & 0x1FFF)]
-        } else if self.selectrtc && self.rambank < 5 {
-            self.rtc_ram_latch[self.rambank]
+        } else if let (true, Some(rtc), 0 ..= 4) = (self.selectrtc, &self.rtc, self.rambank) {
+            rtc.read(self.rambank)
         } else {
             0xFF
         }
@@ -129,14 +77,16 @@ impl MBC for MBC3 {
         match a {
             0x0000 ..= 0x1FFF => self.ram_on = (v & 0x0F) == 0x0A,
             0x2000 ..= 0x3FFF => {
-                self.rombank = match v & 0x7F { 0 => 1, n => n as usize }
+                let mask = if self.mbc30 { 0xFF } else { 0x7F };
+                self.rombank = match v & mask { 0 => 1, n => n as usize }
             },
             0x4000 ..= 0x5FFF => {
                 self.selectrtc = v & 0x8 == 0x8;
-                self.rambank = (v & 0x7) as usize;
+                let mask = if self.selectrtc || self.mbc30 { 0x7 } else { 0x3 };
+                self.rambank = (v & mask) as usize;
             },
-            0x6000 ..= 0x7FFF => self.latch_rtc_reg(),
-            _ => panic!("Could not write to {:04X} (MBC3)", a),
+            0x6000 ..= 0x7FFF => if let Some(rtc) = self.rtc.as_mut() { rtc.latch() },
+            _ => {},
         }
     }
     fn writeram(&mut self, a: u16, v: u8) {
@@ -144,16 +94,8 @@ impl MBC for MBC3 {
         if !self.selectrtc && self.rambank < self.rambanks {
             self.ram[self.rambank * 0x2000 | ((a as usize) & 0x1FFF)] = v;
             self.ram_updated = true;
-        } else if self.selectrtc && self.rambank < 5 {
-            self.calc_rtc_reg();
-            let vmask = match self.rambank {
-                0 | 1 => 0x3F,
-                2 => 0x1F,
-                4 => 0xC1,
-                _ => 0xFF,
-            };
-            self.rtc_ram[self.rambank] = v & vmask;
-            self.calc_rtc_zero();
+        } else if let (true, Some(rtc), 0 ..= 4) = (self.selectrtc, self.rtc.as_mut(), self.rambank) {
+            rtc.write(self.rambank, v);
             self.ram_updated = true;
         }
     }
@@ -162,32 +104,38 @@ impl MBC for MBC3 {
         self.has_battery
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    /// Accepts the RAM on its own, followed by the RTC footer of VBA-M and BGB, or behind the
+    /// 8 byte `rtc_zero` of older saves
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if let Some((ram, footer)) = RtcFooter::split(ramdata, self.ram.len()) {
+            if let Some(rtc) = self.rtc.as_mut() {
+                rtc.load_footer(&footer);
+            }
+            self.ram = ram.to_vec();
+            return Ok(());
+        }
+        if ramdata.len() == self.ram.len() {
+            self.ram = ramdata.to_vec();
+            return Ok(());
+        }
         if ramdata.len() != 8 + self.ram.len() {
-            return Err("Loaded ram is too small");
+            return Err(Error::Save("Loaded ram is too small"));
         }
 
         let (int_bytes, rest) = ramdata.split_at(8);
-        let rtc = u64::from_be_bytes(int_bytes.try_into().unwrap());
-        if self.rtc_zero.is_some() {
-            self.rtc_zero = Some(rtc);
+        let rtc_zero = u64::from_be_bytes(int_bytes.try_into().unwrap());
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load_rtc_zero(rtc_zero);
         }
         self.ram = rest.to_vec();
         Ok(())
     }
 
     fn dumpram(&self) -> Vec<u8> {
-        let rtc = match self.rtc_zero {
-            Some(t) => t,
-            None => 0,
-        };
-
-        let mut file = vec![];
-
-        let mut ok = true;
-        if ok { let rtc_bytes = rtc.to_be_bytes(); ok = file.write_all(&rtc_bytes).is_ok(); };
-        if ok { let _ = file.write_all(&*self.ram); };
-
+        let mut file = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            file.extend_from_slice(&rtc.footer().to_bytes());
+        }
         file
     }
 
@@ -196,4 +144,99 @@ impl MBC for MBC3 {
         self.ram_updated = false;
         result
     }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        if let Some(rtc) = self.rtc.as_mut() { rtc.do_cycle(ticks) }
+    }
+
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        if let Some(rtc) = self.rtc.as_mut() { rtc.set_mode(mode) }
+    }
+
+    fn set_rtc(&mut self, seconds: u64) {
+        if let Some(rtc) = self.rtc.as_mut() { rtc.set(seconds) }
+    }
+
+    fn advance_rtc(&mut self, seconds: u64) {
+        if let Some(rtc) = self.rtc.as_mut() { rtc.advance(seconds) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC3;
+    use crate::rtc::now;
+    use crate::mbc::MBC;
+    use gbcart::{RtcFooter, RTC_FOOTER_LEN};
+
+    fn cartridge(rom_size: u8, ram_size: u8) -> MBC3 {
+        let mut data: Vec<u8> = (0..0x8000 << rom_size).map(|i| (i / 0x4000) as u8).collect();
+        data[0x147] = 0x13;
+        data[0x148] = rom_size;
+        data[0x149] = ram_size;
+        MBC3::new(data).unwrap()
+    }
+
+    #[test]
+    fn mbc3_banking() {
+        let mut mbc = cartridge(0x06, 0x03);
+        mbc.writerom(0x2000, 0xFF);
+        assert_eq!(mbc.readrom(0x4000), 0x7F);
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x01);
+        mbc.writeram(0xA000, 0x42);
+        // Only two bits of RAM bank are connected
+        mbc.writerom(0x4000, 0x05);
+        assert_eq!(mbc.readram(0xA000), 0x42);
+    }
+
+    #[test]
+    fn mbc30_banking() {
+        let mut mbc = cartridge(0x07, 0x05);
+        mbc.writerom(0x2000, 0xFF);
+        assert_eq!(mbc.readrom(0x4000), 0xFF);
+        mbc.writerom(0x2000, 0x80);
+        assert_eq!(mbc.readrom(0x4000), 0x80);
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x07);
+        mbc.writeram(0xA000, 0x42);
+        assert_eq!(mbc.dumpram()[7 * 0x2000], 0x42);
+        mbc.writerom(0x4000, 0x03);
+        assert_eq!(mbc.readram(0xA000), 0x00);
+    }
+
+    #[test]
+    fn rtc_footer() {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = 0x10;
+        data[0x149] = 0x02;
+        let mut mbc = MBC3::new(data.clone()).unwrap();
+        // A clock at 1:02:03 on day 260, saved 10 seconds ago
+        let footer = RtcFooter { registers: [3, 2, 1, 4, 0x01], latched: [3, 2, 1, 4, 0x01], timestamp: now() - 10 };
+        let mut save = vec![0x55; 0x2000];
+        save.extend_from_slice(&footer.to_bytes());
+        mbc.loadram(&save).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x08);
+        assert_eq!(mbc.readram(0xA000), 3);
+        mbc.writerom(0x6000, 0x01);
+        assert!((13..=14).contains(&mbc.readram(0xA000)));
+        mbc.writerom(0x4000, 0x0C);
+        assert_eq!(mbc.readram(0xA000), 0x01);
+
+        let dump = mbc.dumpram();
+        assert_eq!(dump.len(), 0x2000 + RTC_FOOTER_LEN);
+        let saved = RtcFooter::split(&dump, 0x2000).unwrap().1;
+        assert_eq!(saved.registers[1..], [2, 1, 4, 0x01]);
+
+        // Saves with the 8 byte prefix still load
+        let mut legacy = (now() - 3600).to_be_bytes().to_vec();
+        legacy.extend_from_slice(&[0x55; 0x2000]);
+        let mut mbc = MBC3::new(data).unwrap();
+        mbc.loadram(&legacy).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x0A);
+        mbc.writerom(0x6000, 0x01);
+        assert_eq!(mbc.readram(0xA000), 1);
+    }
 }
@@ -1,5 +1,6 @@
-use crate::mbc::{MBC, ram_banks, rom_banks};
-use crate::StrResult;
+use crate::mbc::{RumbleCallback, MBC};
+use crate::{Error, Result};
+use gbcart::{ram_banks, rom_banks};
 
 pub struct MBC5 {
     rom: Vec<u8>,
@@ -11,10 +12,14 @@ pub struct MBC5 {
     has_battery: bool,
     rombanks: usize,
     rambanks: usize,
+    /// Bit 3 of the RAM bank register drives the motor instead of selecting a bank
+    has_rumble: bool,
+    rumble: bool,
+    rumble_callback: Option<RumbleCallback>,
 }
 
 impl MBC5 {
-    pub fn new(data: Vec<u8>) -> StrResult<MBC5> {
+    pub fn new(data: Vec<u8>) -> Result<MBC5> {
         let subtype = data[0x147];
         let has_battery = match subtype {
             0x1B | 0x1E => true,
@@ -26,6 +31,7 @@ impl MBC5 {
         };
         let ramsize = 0x2000 * rambanks;
         let rombanks = rom_banks(data[0x148]);
+        let has_rumble = matches!(subtype, 0x1C ..= 0x1E);
 
         let res = MBC5 {
             rom: data,
@@ -37,10 +43,21 @@ impl MBC5 {
             has_battery: has_battery,
             rombanks: rombanks,
             rambanks: rambanks,
+            has_rumble,
+            rumble: false,
+            rumble_callback: None,
         };
 
         Ok(res)
     }
+
+    fn set_rumble(&mut self, on: bool) {
+        if on == self.rumble { return }
+        self.rumble = on;
+        if let Some(callback) = self.rumble_callback.as_mut() {
+            callback(on);
+        }
+    }
 }
 
 impl MBC for MBC5 {
@@ -50,7 +67,7 @@ impl MBC for MBC5 {
         *self.rom.get(idx).unwrap_or(&0)
     }
     fn readram(&self, a: u16) -> u8 {
-        if !self.ram_on { return 0 }
+        if !self.ram_on || self.rambanks == 0 { return 0 }
         self.ram[self.rambank * 0x2000 | ((a as usize) & 0x1FFF)]
     }
     fn writerom(&mut self, a: u16, v: u8) {
@@ -58,13 +75,19 @@ impl MBC for MBC5 {
             0x0000 ..= 0x1FFF => self.ram_on = v & 0x0F == 0x0A,
             0x2000 ..= 0x2FFF => self.rombank = ((self.rombank & 0x100) | (v as usize)) % self.rombanks,
             0x3000 ..= 0x3FFF => self.rombank = ((self.rombank & 0x0FF) | (((v & 0x1) as usize) << 8)) % self.rombanks,
-            0x4000 ..= 0x5FFF => self.rambank = ((v & 0x0F) as usize) % self.rambanks,
+            0x4000 ..= 0x5FFF => {
+                let bank_mask = if self.has_rumble { 0x07 } else { 0x0F };
+                self.rambank = ((v & bank_mask) as usize) % self.rambanks.max(1);
+                if self.has_rumble {
+                    self.set_rumble(v & 0x08 != 0);
+                }
+            },
             0x6000 ..= 0x7FFF => { /* ? */ },
-            _ => panic!("Could not write to {:04X} (MBC5)", a),
+            _ => {},
         }
     }
     fn writeram(&mut self, a: u16, v: u8) {
-        if self.ram_on == false { return }
+        if self.ram_on == false || self.rambanks == 0 { return }
         self.ram[self.rambank * 0x2000 | ((a as usize) & 0x1FFF)] = v;
         self.ram_updated = true;
     }
@@ -73,9 +96,9 @@ impl MBC for MBC5 {
         self.has_battery
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         if ramdata.len() != self.ram.len() {
-            return Err("Loaded RAM has incorrect length");
+            return Err(Error::Save("Loaded RAM has incorrect length"));
         }
 
         self.ram = ramdata.to_vec();
@@ -92,4 +115,35 @@ impl MBC for MBC5 {
         self.ram_updated = false;
         result
     }
+
+    fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.rumble_callback = Some(callback);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC5;
+    use crate::mbc::MBC;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn rumble_bit_drives_motor() {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = 0x1E;
+        data[0x149] = 0x03;
+        let mut mbc = MBC5::new(data).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        mbc.set_rumble_callback(Box::new(move |on| sink.lock().unwrap().push(on)));
+
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x0B);
+        assert_eq!(mbc.rambank, 3);
+        mbc.writeram(0xA000, 0x42);
+        mbc.writerom(0x4000, 0x0B);
+        mbc.writerom(0x4000, 0x03);
+        assert_eq!(mbc.readram(0xA000), 0x42);
+        assert_eq!(*events.lock().unwrap(), [true, false]);
+    }
 }
@@ -0,0 +1,226 @@
+use crate::mbc::MBC;
+use crate::{Error, Result};
+
+const ROM_BANK_SIZE: usize = 0x2000;
+const RAM_BANK_SIZE: usize = 0x1000;
+const RAM_SIZE: usize = 8 * RAM_BANK_SIZE;
+/// The cartridge holds a 1 MiB Macronix flash chip next to its ROM
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_MANUFACTURER: u8 = 0xC2;
+const FLASH_DEVICE: u8 = 0x81;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum FlashState {
+    Read,
+    Unlock1,
+    Unlock2,
+    Identify,
+    Program,
+    /// Received 0x80, which has to be followed by a second unlock sequence
+    Erase,
+    EraseUnlock1,
+    EraseUnlock2,
+}
+
+/// One of the two 8 KiB windows at 0x4000 and 0x6000, which map ROM or flash
+#[derive(Clone, Copy)]
+struct Window {
+    bank: usize,
+    flash: bool,
+}
+
+pub struct MBC6 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    flash: Vec<u8>,
+    windows: [Window; 2],
+    rambanks: [usize; 2],
+    ram_on: bool,
+    flash_on: bool,
+    flash_write_on: bool,
+    flash_state: FlashState,
+    ram_updated: bool,
+}
+
+impl MBC6 {
+    pub fn new(data: Vec<u8>) -> Result<MBC6> {
+        let res = MBC6 {
+            rom: data,
+            ram: vec![0; RAM_SIZE],
+            flash: vec![0xFF; FLASH_SIZE],
+            windows: [Window { bank: 0, flash: false }; 2],
+            rambanks: [0; 2],
+            ram_on: false,
+            flash_on: false,
+            flash_write_on: false,
+            flash_state: FlashState::Read,
+            ram_updated: false,
+        };
+
+        Ok(res)
+    }
+
+    fn flash_address(&self, window: Window, a: u16) -> usize {
+        (window.bank * ROM_BANK_SIZE + (a as usize & (ROM_BANK_SIZE - 1))) % FLASH_SIZE
+    }
+
+    fn ram_address(&self, a: u16) -> usize {
+        let bank = self.rambanks[((a as usize) >> 12) & 1];
+        bank * RAM_BANK_SIZE + (a as usize & (RAM_BANK_SIZE - 1))
+    }
+
+    /// Runs the AMD style command sequences of the flash chip
+    fn write_flash(&mut self, address: usize, v: u8) {
+        let command_address = address & 0x7FFF;
+        self.flash_state = match (self.flash_state, command_address, v) {
+            (_, _, 0xF0) => FlashState::Read,
+            (FlashState::Read, 0x5555, 0xAA) | (FlashState::Identify, 0x5555, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2AAA, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0x90) => FlashState::Identify,
+            (FlashState::Unlock2, 0x5555, 0xA0) => FlashState::Program,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::Erase,
+            (FlashState::Erase, 0x5555, 0xAA) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, 0x2AAA, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, 0x5555, 0x10) => {
+                self.flash.fill(0xFF);
+                self.ram_updated = true;
+                FlashState::Read
+            },
+            (FlashState::EraseUnlock2, _, 0x30) => {
+                let start = address - address % ROM_BANK_SIZE;
+                self.flash[start..start + ROM_BANK_SIZE].fill(0xFF);
+                self.ram_updated = true;
+                FlashState::Read
+            },
+            (FlashState::Program, _, _) => {
+                // Programming can only clear bits, erasing sets them again
+                self.flash[address] &= v;
+                self.ram_updated = true;
+                FlashState::Read
+            },
+            _ => FlashState::Read,
+        };
+    }
+}
+
+impl MBC for MBC6 {
+    fn readrom(&self, a: u16) -> u8 {
+        if a < 0x4000 {
+            return *self.rom.get(a as usize).unwrap_or(&0xFF);
+        }
+        let window = self.windows[((a as usize) >> 13) & 1];
+        if !window.flash {
+            let idx = window.bank * ROM_BANK_SIZE + (a as usize & (ROM_BANK_SIZE - 1));
+            return *self.rom.get(idx % self.rom.len()).unwrap_or(&0xFF);
+        }
+        if !self.flash_on { return 0xFF }
+        let address = self.flash_address(window, a);
+        match self.flash_state {
+            FlashState::Identify => match address & 0x1 {
+                0 => FLASH_MANUFACTURER,
+                _ => FLASH_DEVICE,
+            },
+            // Erasing and programming finish right away, so the status polling sees the data
+            _ => self.flash[address],
+        }
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if !self.ram_on { return 0xFF }
+        self.ram[self.ram_address(a)]
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x03FF => self.ram_on = v & 0x0F == 0x0A,
+            0x0400 ..= 0x07FF => self.rambanks[0] = (v & 0x07) as usize,
+            0x0800 ..= 0x0BFF => self.rambanks[1] = (v & 0x07) as usize,
+            0x0C00 ..= 0x0FFF => self.flash_on = v & 0x01 == 0x01,
+            0x1000 ..= 0x1FFF => self.flash_write_on = v & 0x01 == 0x01,
+            0x2000 ..= 0x27FF => self.windows[0].bank = (v & 0x7F) as usize,
+            0x2800 ..= 0x2FFF => self.windows[0].flash = v == 0x08,
+            0x3000 ..= 0x37FF => self.windows[1].bank = (v & 0x7F) as usize,
+            0x3800 ..= 0x3FFF => self.windows[1].flash = v == 0x08,
+            0x4000 ..= 0x7FFF => {
+                let window = self.windows[((a as usize) >> 13) & 1];
+                if window.flash && self.flash_on && self.flash_write_on {
+                    let address = self.flash_address(window, a);
+                    self.write_flash(address, v);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if !self.ram_on { return }
+        let address = self.ram_address(a);
+        self.ram[address] = v;
+        self.ram_updated = true;
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    /// The save holds the RAM followed by the flash
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != RAM_SIZE + FLASH_SIZE {
+            return Err(Error::Save("Loaded RAM has incorrect length"));
+        }
+
+        let (ram, flash) = ramdata.split_at(RAM_SIZE);
+        self.ram = ram.to_vec();
+        self.flash = flash.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let mut file = self.ram.clone();
+        file.extend_from_slice(&self.flash);
+        file
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC6;
+    use crate::mbc::MBC;
+
+    /// Writes `v` to `address` in the flash, through window A
+    fn flash_write(mbc: &mut MBC6, address: usize, v: u8) {
+        mbc.writerom(0x2000, (address / 0x2000) as u8);
+        mbc.writerom(0x4000 + (address % 0x2000) as u16, v);
+    }
+
+    #[test]
+    fn banks_rom_and_programs_flash() {
+        let data: Vec<u8> = (0..0x100000).map(|i| (i / 0x2000) as u8).collect();
+        let mut mbc = MBC6::new(data).unwrap();
+        mbc.writerom(0x2000, 0x12);
+        mbc.writerom(0x3000, 0x34);
+        assert_eq!((mbc.readrom(0x4000), mbc.readrom(0x6000)), (0x12, 0x34));
+
+        mbc.writerom(0x0C00, 0x01);
+        mbc.writerom(0x1000, 0x01);
+        mbc.writerom(0x2800, 0x08);
+        for (address, v) in [(0x5555, 0xAA), (0x2AAA, 0x55), (0x5555, 0xA0), (0x20010, 0x5A)] {
+            flash_write(&mut mbc, address, v);
+        }
+        mbc.writerom(0x2000, 0x10);
+        assert_eq!(mbc.readrom(0x4010), 0x5A);
+        assert_eq!(mbc.dumpram()[0x8000 + 0x20010], 0x5A);
+
+        for (address, v) in [(0x5555, 0xAA), (0x2AAA, 0x55), (0x5555, 0x90)] {
+            flash_write(&mut mbc, address, v);
+        }
+        assert_eq!((mbc.readrom(0x4000), mbc.readrom(0x4001)), (0xC2, 0x81));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::mbc::MBC;
+use crate::{Error, Result};
+use gbcart::rom_banks;
+
+/// Number of 16 bit words in the 93LC56 EEPROM
+const EEPROM_WORDS: usize = 128;
+/// Accelerometer reading of a cartridge lying flat
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+/// Change of the accelerometer reading for a tilt of 1 g
+const ACCEL_PER_G: f32 = 0x70 as f32;
+
+pub struct MBC7 {
+    rom: Vec<u8>,
+    rombank: usize,
+    rombanks: usize,
+    ram_on: bool,
+    ram_on2: bool,
+    eeprom: Eeprom,
+    tilt: (f32, f32),
+    latch_ready: bool,
+    latched: (u16, u16),
+}
+
+impl MBC7 {
+    pub fn new(data: Vec<u8>) -> Result<MBC7> {
+        let rombanks = rom_banks(data[0x148]);
+        Ok(MBC7 {
+            rom: data,
+            rombank: 1,
+            rombanks,
+            ram_on: false,
+            ram_on2: false,
+            eeprom: Eeprom::new(),
+            tilt: (0.0, 0.0),
+            latch_ready: false,
+            latched: (0x8000, 0x8000),
+        })
+    }
+
+    fn accelerometer(&self) -> (u16, u16) {
+        let (x, y) = self.tilt;
+        let x = (ACCEL_CENTER - x * ACCEL_PER_G).round() as u16;
+        let y = (ACCEL_CENTER + y * ACCEL_PER_G).round() as u16;
+        (x, y)
+    }
+}
+
+impl MBC for MBC7 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if !self.ram_on || !self.ram_on2 || a >= 0xB000 { return 0xFF }
+        match (a >> 4) & 0x0F {
+            0x2 => self.latched.0 as u8,
+            0x3 => (self.latched.0 >> 8) as u8,
+            0x4 => self.latched.1 as u8,
+            0x5 => (self.latched.1 >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        match a {
+            0x0000 ..= 0x1FFF => self.ram_on = v & 0x0F == 0x0A,
+            0x2000 ..= 0x3FFF => self.rombank = ((v & 0x7F) as usize) % self.rombanks,
+            0x4000 ..= 0x5FFF => self.ram_on2 = v == 0x40,
+            0x6000 ..= 0x7FFF => {},
+            _ => {},
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if !self.ram_on || !self.ram_on2 || a >= 0xB000 { return }
+        match (a >> 4) & 0x0F {
+            0x0 if v == 0x55 => {
+                self.latch_ready = true;
+                self.latched = (0x8000, 0x8000);
+            },
+            0x1 if v == 0xAA && self.latch_ready => {
+                self.latch_ready = false;
+                self.latched = self.accelerometer();
+            },
+            0x8 => self.eeprom.write(v),
+            _ => {},
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != EEPROM_WORDS * 2 {
+            return Err(Error::Save("Loaded RAM has incorrect length"));
+        }
+        for (word, bytes) in self.eeprom.data.iter_mut().zip(ramdata.chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.eeprom.data.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.eeprom.updated;
+        self.eeprom.updated = false;
+        result
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+}
+
+/// A 93LC56 serial EEPROM organised as 128 words of 16 bits
+struct Eeprom {
+    data: [u16; EEPROM_WORDS],
+    updated: bool,
+    write_enabled: bool,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    /// The DO line, high while the EEPROM is idle
+    output: bool,
+    /// Bits clocked in since the start bit, including it
+    command: u32,
+    command_bits: u32,
+    /// Data still to be clocked out by a read
+    read_data: u16,
+    read_bits: u32,
+    read_address: usize,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            data: [0xFFFF; EEPROM_WORDS],
+            updated: false,
+            write_enabled: false,
+            cs: false,
+            clk: false,
+            di: false,
+            output: true,
+            command: 0,
+            command_bits: 0,
+            read_data: 0,
+            read_bits: 0,
+            read_address: 0,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.output as u8
+    }
+
+    fn write(&mut self, v: u8) {
+        let (cs, clk, di) = (v & 0x80 != 0, v & 0x40 != 0, v & 0x02 != 0);
+        if !cs || !self.cs {
+            // Selecting the chip starts a new command
+            self.command = 0;
+            self.command_bits = 0;
+            self.read_bits = 0;
+            self.output = true;
+        }
+        else if clk && !self.clk {
+            self.clock_in(di);
+        }
+        self.cs = cs;
+        self.clk = clk;
+        self.di = di;
+    }
+
+    fn clock_in(&mut self, di: bool) {
+        if self.read_bits > 0 {
+            self.output = self.read_data & 0x8000 != 0;
+            self.read_data <<= 1;
+            self.read_bits -= 1;
+            if self.read_bits == 0 {
+                // Keeping the clock running reads the following words
+                self.read_address = (self.read_address + 1) % EEPROM_WORDS;
+                self.start_read();
+            }
+            return;
+        }
+        if self.command_bits == u32::MAX || (self.command_bits == 0 && !di) {
+            // Zeros before the start bit are ignored
+            return;
+        }
+        self.command = self.command << 1 | di as u32;
+        self.command_bits += 1;
+
+        // Start bit, 2 bit opcode and 8 bit address, where the top address bit is not used
+        let address = (self.command as usize) & (EEPROM_WORDS - 1);
+        let opcode = (self.command >> self.command_bits.saturating_sub(3)) & 0x03;
+        match (self.command_bits, opcode) {
+            (11, 0b10) => {
+                self.read_address = address;
+                // A dummy zero comes before the data
+                self.output = false;
+                self.start_read();
+            },
+            (11, 0b11) => {
+                self.program(Some(address), 0xFFFF);
+                self.finish();
+            },
+            (11, 0b00) => {
+                match (self.command >> 6) & 0x03 {
+                    0b00 => self.write_enabled = false,
+                    0b11 => self.write_enabled = true,
+                    0b10 => self.program(None, 0xFFFF),
+                    _ => return, // WRAL needs its data first
+                }
+                self.finish();
+            },
+            (27, opcode) => {
+                let value = self.command as u16;
+                let address = (self.command >> 16) as usize & (EEPROM_WORDS - 1);
+                match opcode {
+                    0b01 => self.program(Some(address), value),
+                    _ => self.program(None, value),
+                }
+                self.finish();
+            },
+            _ => {},
+        }
+    }
+
+    fn start_read(&mut self) {
+        self.read_data = self.data[self.read_address];
+        self.read_bits = 16;
+    }
+
+    /// Writes `value` to the word at `address`, or to all of them
+    fn program(&mut self, address: Option<usize>, value: u16) {
+        if !self.write_enabled {
+            return;
+        }
+        match address {
+            Some(address) => self.data[address] = value,
+            None => self.data.fill(value),
+        }
+        self.updated = true;
+    }
+
+    /// Ignores further bits until the chip is selected again
+    fn finish(&mut self) {
+        self.command_bits = u32::MAX;
+        self.output = true;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC7;
+    use crate::mbc::MBC;
+
+    const WRITE: u32 = 1;
+    const READ: u32 = 2;
+    /// The address bits which turn opcode 0 into EWEN
+    const EWEN: u32 = 0xC0;
+
+    fn mbc7() -> MBC7 {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = 0x22;
+        let mut mbc = MBC7::new(data).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        mbc.writerom(0x4000, 0x40);
+        mbc
+    }
+
+    /// Clocks `count` bits of `value` into the EEPROM, returning the bits read from DO
+    fn clock_bits(mbc: &mut MBC7, value: u32, count: u32) -> u32 {
+        let mut output = 0;
+        for bit in (0..count).rev() {
+            let di = ((value >> bit) & 1) as u8;
+            mbc.writeram(0xA080, 0x80 | di << 1);
+            mbc.writeram(0xA080, 0xC0 | di << 1);
+            output = output << 1 | (mbc.readram(0xA080) & 1) as u32;
+        }
+        output
+    }
+
+    /// Selects the EEPROM and sends the start bit, `opcode` and `address`, followed by `data`
+    fn command(mbc: &mut MBC7, opcode: u32, address: u32, data: Option<u16>) {
+        mbc.writeram(0xA080, 0x00);
+        mbc.writeram(0xA080, 0x80);
+        clock_bits(mbc, 1 << 10 | opcode << 8 | address, 11);
+        if let Some(data) = data {
+            clock_bits(mbc, data as u32, 16);
+        }
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = mbc7();
+        // Writes are ignored until EWEN
+        command(&mut mbc, WRITE, 5, Some(0x1234));
+        assert!(!mbc.check_and_reset_ram_updated());
+        command(&mut mbc, 0, EWEN, None);
+        command(&mut mbc, WRITE, 5, Some(0x1234));
+        assert!(mbc.check_and_reset_ram_updated());
+        command(&mut mbc, READ, 5, None);
+        assert_eq!(clock_bits(&mut mbc, 0, 16), 0x1234);
+        assert_eq!(&mbc.dumpram()[10..12], &[0x34, 0x12]);
+
+        mbc.loadram(&[0xAB; 256]).unwrap();
+        command(&mut mbc, READ, 0, None);
+        assert_eq!(clock_bits(&mut mbc, 0, 16), 0xABAB);
+    }
+
+    #[test]
+    fn latches_accelerometer() {
+        let mut mbc = mbc7();
+        mbc.set_tilt(1.0, -0.5);
+        mbc.writeram(0xA000, 0x55);
+        assert_eq!((mbc.readram(0xA020), mbc.readram(0xA030)), (0x00, 0x80));
+        mbc.writeram(0xA010, 0xAA);
+        let x = mbc.readram(0xA020) as u16 | (mbc.readram(0xA030) as u16) << 8;
+        let y = mbc.readram(0xA040) as u16 | (mbc.readram(0xA050) as u16) << 8;
+        assert_eq!((x, y), (0x81D0 - 0x70, 0x81D0 - 0x38));
+        // The values stay latched until the next erase
+        mbc.set_tilt(0.0, 0.0);
+        mbc.writeram(0xA010, 0xAA);
+        assert_eq!(mbc.readram(0xA020), (0x81D0 - 0x70) as u8);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::mbc::MBC;
+use crate::{Error, Result};
+use gbcart::{ram_banks, CartridgeHeader};
+
+/// The menu and the header of the MMM01 live in the last 32 KiB of the ROM
+const MENU_SIZE: usize = 0x8000;
+
+/// Whether `data` is an MMM01 multicart, whose first bank holds the header of the first game
+pub fn is_mmm01(data: &[u8]) -> bool {
+    data.len() >= MENU_SIZE
+        && CartridgeHeader::from_bytes(&data[data.len() - MENU_SIZE..])
+            .is_some_and(|header| matches!(header.cartridge_type, 0x0B ..= 0x0D))
+}
+
+/// The MMM01 boots into the menu at the end of the ROM. The menu selects the banks of a game and
+/// then locks the mapper, which from then on works like an MBC1 limited to that game.
+pub struct MMM01 {
+    rom: Vec<u8>,
+    /// Where the menu starts, which is mapped until a game is selected
+    menu: usize,
+    ram: Vec<u8>,
+    has_battery: bool,
+    mapped: bool,
+    ram_on: bool,
+    /// Bits 0-4 of the ROM bank, the part an MBC1 game can switch
+    rom_low: usize,
+    /// Bits 5-8 of the ROM bank, which select the game
+    rom_high: usize,
+    /// Bits 1-4 of `rom_low` which the game cannot change
+    rom_mask: usize,
+    ram_low: usize,
+    ram_high: usize,
+    banking_mode: u8,
+    /// The game cannot switch the banking mode
+    mode_locked: bool,
+    ram_updated: bool,
+}
+
+impl MMM01 {
+    pub fn new(data: Vec<u8>) -> Result<MMM01> {
+        // Some dumps start with the menu, so the MMM01 header is in bank 0
+        let menu = if is_mmm01(&data) { data.len() - MENU_SIZE } else { 0 };
+        let header = CartridgeHeader::from_bytes(&data[menu..]).ok_or(Error::Rom("Rom size to small"))?;
+        let rambanks = ram_banks(header.ram_size);
+
+        let res = MMM01 {
+            rom: data,
+            menu,
+            ram: vec![0; rambanks * 0x2000],
+            has_battery: header.cartridge_type == 0x0D,
+            mapped: false,
+            ram_on: false,
+            rom_low: 0,
+            rom_high: 0,
+            rom_mask: 0,
+            ram_low: 0,
+            ram_high: 0,
+            banking_mode: 0,
+            mode_locked: false,
+            ram_updated: false,
+        };
+
+        Ok(res)
+    }
+
+    fn writable_rom_bits(&self) -> usize {
+        0x1F & !(self.rom_mask << 1)
+    }
+
+    fn rom_offset(&self, a: u16) -> usize {
+        if !self.mapped {
+            return self.menu + a as usize;
+        }
+        let writable = self.writable_rom_bits();
+        let mut bank = (self.rom_high << 5) | self.rom_low;
+        if a < 0x4000 {
+            bank &= !writable;
+        } else if self.rom_low & writable == 0 {
+            // Bank 0 of the game cannot be mapped at 0x4000, just like on an MBC1
+            bank |= 1;
+        }
+        (bank * 0x4000 + (a as usize & 0x3FFF)) % self.rom.len()
+    }
+
+    fn ram_address(&self, a: u16) -> Option<usize> {
+        if self.ram.is_empty() { return None }
+        let bank = (self.ram_high << 2) | if self.banking_mode == 1 || !self.mapped { self.ram_low } else { 0 };
+        Some((bank * 0x2000 + (a as usize & 0x1FFF)) % self.ram.len())
+    }
+}
+
+impl MBC for MMM01 {
+    fn readrom(&self, a: u16) -> u8 {
+        *self.rom.get(self.rom_offset(a)).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if !self.ram_on { return 0xFF }
+        self.ram_address(a).map_or(0xFF, |address| self.ram[address])
+    }
+
+    fn writerom(&mut self, a: u16, v: u8) {
+        let v = v as usize;
+        match a {
+            0x0000 ..= 0x1FFF => {
+                self.ram_on = v & 0x0F == 0x0A;
+                if !self.mapped && v & 0x40 != 0 {
+                    self.mapped = true;
+                }
+            },
+            0x2000 ..= 0x3FFF => {
+                let writable = if self.mapped { self.writable_rom_bits() } else { 0x1F };
+                self.rom_low = (self.rom_low & !writable) | (v & writable);
+                if !self.mapped {
+                    self.rom_high = (self.rom_high & 0x0C) | ((v >> 5) & 0x03);
+                }
+            },
+            0x4000 ..= 0x5FFF => {
+                self.ram_low = v & 0x03;
+                if !self.mapped {
+                    self.ram_high = (v >> 2) & 0x03;
+                    self.rom_high = (self.rom_high & 0x03) | (((v >> 4) & 0x03) << 2);
+                    self.mode_locked = v & 0x40 != 0;
+                }
+            },
+            0x6000 ..= 0x7FFF => {
+                if !self.mapped {
+                    self.rom_mask = (v >> 2) & 0x0F;
+                }
+                if !(self.mapped && self.mode_locked) {
+                    self.banking_mode = (v & 0x01) as u8;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if !self.ram_on { return }
+        if let Some(address) = self.ram_address(a) {
+            self.ram[address] = v;
+            self.ram_updated = true;
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        self.has_battery
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != self.ram.len() {
+            return Err(Error::Save("Loaded RAM has incorrect length"));
+        }
+
+        self.ram = ramdata.to_vec();
+
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        self.ram.to_vec()
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_mmm01, MMM01};
+    use crate::mbc::MBC;
+
+    #[test]
+    fn boots_menu_and_maps_game() {
+        // 64 banks, each starting with its own number, and the menu header in the last 32 KiB
+        let mut data: Vec<u8> = (0..0x40 * 0x4000).map(|i| if i % 0x4000 == 0 { (i / 0x4000) as u8 } else { 0 }).collect();
+        let menu = data.len() - 0x8000;
+        data[menu + 0x147] = 0x0D;
+        data[menu + 0x149] = 0x03;
+        assert!(is_mmm01(&data));
+        let mut mbc = MMM01::new(data).unwrap();
+        assert!(mbc.is_battery_backed());
+        assert_eq!(mbc.readrom(0x0000), 0x3E);
+        assert_eq!(mbc.readrom(0x4000), 0x3F);
+
+        // Select the 128 KiB game at bank 0x10, locking bank bits 3 and 4
+        mbc.writerom(0x2000, 0x10);
+        mbc.writerom(0x6000, 0b1100 << 2);
+        mbc.writerom(0x0000, 0x40);
+        assert_eq!((mbc.readrom(0x0000), mbc.readrom(0x4000)), (0x10, 0x11));
+        mbc.writerom(0x2000, 0x05);
+        assert_eq!((mbc.readrom(0x0000), mbc.readrom(0x4000)), (0x10, 0x15));
+        // The game cannot leave its banks any more
+        mbc.writerom(0x2000, 0x1F);
+        mbc.writerom(0x4000, 0x30);
+        assert_eq!(mbc.readrom(0x4000), 0x17);
+    }
+
+    #[test]
+    fn boots_menu_from_bank_0() {
+        // A test ROM under 32 KiB, which is its own menu
+        let mut data = vec![0; 0x4000];
+        data[0x100] = 0x42;
+        data[0x147] = 0x0B;
+        assert!(!is_mmm01(&data));
+        let mbc = MMM01::new(data).unwrap();
+        assert!(!mbc.is_battery_backed());
+        assert_eq!(mbc.readrom(0x0100), 0x42);
+        assert_eq!(mbc.readrom(0x4000), 0xFF);
+
+        assert!(MMM01::new(vec![0; 0x100]).is_err());
+    }
+}
@@ -1,14 +1,38 @@
-use crate::StrResult;
+use crate::camera::ImageSource;
+use crate::rtc::RtcMode;
+use crate::{Error, Result};
+use gbcart::{CartridgeHeader, HEADER_END};
 use std::io;
 use std::io::prelude::*;
-use std::fs::{self, File};
+use std::fs;
 
 mod mbc0;
 mod mbc1;
 mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod tama5;
+mod huc1;
+mod huc3;
+mod camera;
 mod cartridge_reader;
+#[cfg(test)]
+mod bus_simulator;
+mod hardware_mbc;
+mod save_convert;
+mod save_file;
+mod source;
+
+pub use self::cartridge_reader::GpioCartridge;
+pub use self::hardware_mbc::HardwareMBC;
+pub use self::save_convert::{export_save, import_save};
+pub use self::source::{CartridgeSource, DatCheckedCartridge, FileCartridge, MemoryCartridge, MockCartridge};
+
+/// Called with the new state of the motor of a rumble cartridge
+pub type RumbleCallback = Box<dyn FnMut(bool) + Send>;
 
 pub trait MBC : Send {
     fn readrom(&self, a: u16) -> u8;
@@ -18,73 +42,173 @@ pub trait MBC : Send {
     fn check_and_reset_ram_updated(&mut self) -> bool;
 
     fn is_battery_backed(&self) -> bool;
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()>;
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()>;
     fn dumpram(&self) -> Vec<u8>;
 
+    fn header(&self) -> CartridgeHeader {
+        // Reads whatever the cartridge returns, so the header can be parsed from any ROM
+        let data: Vec<u8> = (0..HEADER_END as u16).map(|a| self.readrom(a)).collect();
+        CartridgeHeader::from_bytes(&data).expect("the whole header was read")
+    }
+
     fn romname(&self) -> String {
-        const TITLE_START : u16 = 0x134;
-        const CGB_FLAG : u16 = 0x143;
+        self.header().title
+    }
 
-        let title_size = match self.readrom(CGB_FLAG) & 0x80 {
-            0x80 => 11,
-            _ => 16,
-        };
+    /// Tilts the cartridge by `x` and `y` times the gravity, for cartridges with an accelerometer.
+    /// Positive values tilt it to the right and away from the player.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
 
-        let mut result = String::with_capacity(title_size as usize);
+    /// Replaces the image seen by cartridges with a camera
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
 
-        for i in 0..title_size {
-            match self.readrom(TITLE_START + i) {
-                0 => break,
-                v => result.push(v as char),
-            }
-        }
+    /// Runs the hardware of the cartridge for `ticks` of the 4 MiHz clock
+    fn do_cycle(&mut self, _ticks: u32) {}
 
-        result
+    /// Selects what drives the real time clock of the cartridge
+    fn set_rtc_mode(&mut self, _mode: RtcMode) {}
+
+    /// Sets the real time clock to `seconds` after day 0
+    fn set_rtc(&mut self, _seconds: u64) {}
+
+    /// Runs the real time clock forward by `seconds`
+    fn advance_rtc(&mut self, _seconds: u64) {}
+
+    /// Registers the function which follows the motor of rumble cartridges
+    fn set_rumble_callback(&mut self, _callback: RumbleCallback) {}
+
+    /// Writes the save RAM to wherever it is kept, for cartridges which keep it anywhere
+    fn save(&mut self) -> Result<()> {
+        Ok(())
     }
 }
 
-pub fn get_mbc(data: Vec<u8>, skip_checksum: bool) -> StrResult<Box<dyn MBC+'static>> {
-    if data.len() < 0x150 { return Err("Rom size to small"); }
-    if !skip_checksum {
-        check_checksum(&data)?;
+#[cfg(test)]
+pub fn get_mbc(source: &mut dyn CartridgeSource, skip_checksum: bool) -> Result<Box<dyn MBC+'static>> {
+    mbc_from_rom(source.read_rom()?, skip_checksum)
+}
+
+fn mbc_from_rom(data: Vec<u8>, skip_checksum: bool) -> Result<Box<dyn MBC+'static>> {
+    let header = CartridgeHeader::from_bytes(&data).ok_or(Error::Rom("Rom size to small"))?;
+    if !skip_checksum && !header.checksum_ok() {
+        return Err(Error::InvalidChecksum);
+    }
+    // The header of an MMM01 is usually at the end, in front of the menu
+    if mmm01::is_mmm01(&data) {
+        return mmm01::MMM01::new(data).map(|v| Box::new(v) as Box<dyn MBC>);
     }
-    match data[0x147] {
+    // The mappers wrap the selected bank around the number of banks
+    if header.rom_banks() == 0 {
+        return Err(Error::Rom("Unknown ROM size in the header"));
+    }
+    match header.cartridge_type {
         0x00 => mbc0::MBC0::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
         0x01 ..= 0x03 => mbc1::MBC1::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
         0x05 ..= 0x06 => mbc2::MBC2::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        0x0B ..= 0x0D => mmm01::MMM01::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
         0x0F ..= 0x13 => mbc3::MBC3::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
         0x19 ..= 0x1E => mbc5::MBC5::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
-        _ => { Err("Unsupported MBC type") },
+        0x20 => mbc6::MBC6::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        0x22 => mbc7::MBC7::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        0xFC => camera::PocketCamera::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        0xFD => tama5::TAMA5::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        0xFE => huc3::HuC3::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        0xFF => huc1::HuC1::new(data).map(|v| Box::new(v) as Box<dyn MBC>),
+        other => Err(Error::UnsupportedMbc(other)),
     }
 }
 
+/// Emulated time between checks for changes to the save RAM, one second
+const AUTOSAVE_TICKS: u32 = 4194304;
+
 pub struct FileBackedMBC {
     mbc: Box<dyn MBC>,
-    rampath: std::path::PathBuf,
+    rampath: Option<std::path::PathBuf>,
+    /// Ticks since the last check for changes to the save RAM
+    autosave_ticks: u32,
+    /// The save RAM changed since it was last written to the file
+    dirty: bool,
+    /// The save RAM changed since `check_and_reset_ram_updated` was last called
+    ram_updated: bool,
+    /// The save of the previous session was already copied to the backups
+    backed_up: bool,
+    /// The last save failed, so the next failure is not reported again
+    save_failing: bool,
 }
 
 impl FileBackedMBC {
-    pub fn new(skip_checksum: bool) -> StrResult<FileBackedMBC> {
-        let data = cartridge_reader::read_cartridge();
-        let mut mbc = get_mbc(data, skip_checksum)?;
-
-        let rampath = std::path::PathBuf::from(mbc.romname()).with_extension("gbsave");
+    pub fn new(source: &mut dyn CartridgeSource, skip_checksum: bool) -> Result<FileBackedMBC> {
+        let data = source.read_rom()?;
+        let rampath = source.save_path(&data);
+        let mut mbc = mbc_from_rom(data, skip_checksum)?;
 
-        if mbc.is_battery_backed() {
-            match fs::File::open(&rampath) {
+        if let (true, Some(path)) = (mbc.is_battery_backed(), &rampath) {
+            let legacy = source.legacy_save_path(&mbc.romname()).filter(|legacy| legacy.is_file());
+            if let (false, Some(legacy)) = (path.exists(), legacy) {
+                match save_file::migrate(&legacy, path) {
+                    Ok(()) => println!("Moved the save file {} to {}", legacy.display(), path.display()),
+                    Err(e) => return Err(Error::SaveFile("Could not move the old save file to", path.clone(), e)),
+                }
+            }
+            match fs::File::open(path) {
                 Ok(mut file) => {
                     let mut ramdata: Vec<u8> = vec![];
                     match file.read_to_end(&mut ramdata) {
-                        Err(..) => return Err("Error while reading existing save file"),
+                        Err(e) => return Err(Error::SaveFile("Error while reading existing save file", path.clone(), e)),
                         Ok(..) => { mbc.loadram(&ramdata)?; },
                     }
                 },
                 Err(ref e) if e.kind() == io::ErrorKind::NotFound => {},
-                Err(_) => return Err("Error loading existing save file"),
+                Err(e) => return Err(Error::SaveFile("Error loading existing save file", path.clone(), e)),
             }
         }
 
-        Ok(FileBackedMBC { rampath, mbc })
+        Ok(FileBackedMBC {
+            mbc,
+            rampath,
+            autosave_ticks: 0,
+            dirty: false,
+            ram_updated: false,
+            backed_up: false,
+            save_failing: false,
+        })
+    }
+
+    /// Writes the save file, after moving the save of the previous session to the backups
+    fn write_save(&mut self) -> io::Result<()> {
+        let path = match (self.mbc.is_battery_backed(), &self.rampath) {
+            (true, Some(path)) => path,
+            _ => return Ok(()),
+        };
+        if !self.backed_up {
+            save_file::create_parent(path)?;
+            save_file::rotate_backups(path)?;
+            self.backed_up = true;
+        }
+        save_file::write_atomic(path, &self.mbc.dumpram())?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Saves and reports a failure, unless the previous attempt already failed
+    fn save_and_report(&mut self) {
+        match self.write_save() {
+            Ok(()) => self.save_failing = false,
+            Err(e) => {
+                if !self.save_failing {
+                    let path = self.rampath.as_deref().unwrap_or(std::path::Path::new(""));
+                    eprintln!("Could not save to {}: {}", path.display(), e);
+                }
+                self.save_failing = true;
+            },
+        }
+    }
+
+    fn poll_ram_updated(&mut self) {
+        if self.mbc.check_and_reset_ram_updated() {
+            self.dirty = true;
+            self.ram_updated = true;
+        }
     }
 }
 
@@ -110,7 +234,7 @@ impl MBC for FileBackedMBC {
         self.mbc.is_battery_backed()
     }
 
-    fn loadram(&mut self, ramdata: &[u8]) -> StrResult<()> {
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
         self.mbc.loadram(ramdata)
     }
 
@@ -119,73 +243,218 @@ impl MBC for FileBackedMBC {
     }
 
     fn check_and_reset_ram_updated(&mut self) -> bool {
-        self.mbc.check_and_reset_ram_updated()
+        self.poll_ram_updated();
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
     }
-}
 
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.mbc.set_tilt(x, y)
+    }
 
-impl Drop for FileBackedMBC {
-    fn drop(&mut self) {
-        if self.mbc.is_battery_backed() {
-            // TODO: error handling
-            let mut file = match fs::File::create(&self.rampath) {
-                Ok(f) => f,
-                Err(..) => return,
-            };
-            let _ = file.write_all(&self.mbc.dumpram());
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.mbc.set_image_source(source)
+    }
+
+    fn do_cycle(&mut self, ticks: u32) {
+        self.mbc.do_cycle(ticks);
+        self.autosave_ticks += ticks;
+        if self.autosave_ticks >= AUTOSAVE_TICKS {
+            self.autosave_ticks = 0;
+            self.poll_ram_updated();
+            if self.dirty {
+                self.save_and_report();
+            }
         }
     }
-}
 
-fn ram_banks(v: u8) -> usize {
-    match v {
-        1 =>
-            // "Listed in various unofficial docs as 2 KiB. However, a 2 KiB RAM chip was never
-            // used in a cartridge. The source of this value is unknown."
-            // Needed by some test roms. As we only deal in whole banks, just make it 1 8KiB bank.
-            1,
-        2 => 1,
-        3 => 4,
-        4 => 16,
-        5 => 8,
-        _ => 0,
+    fn set_rtc_mode(&mut self, mode: RtcMode) {
+        self.mbc.set_rtc_mode(mode)
     }
-}
 
-fn rom_banks(v: u8) -> usize {
-    if v <= 8 {
-        2 << v
+    fn set_rtc(&mut self, seconds: u64) {
+        self.mbc.set_rtc(seconds)
     }
-    else {
-        0
+
+    fn advance_rtc(&mut self, seconds: u64) {
+        self.mbc.advance_rtc(seconds)
     }
-}
 
-fn check_checksum(data: &[u8]) -> StrResult<()> {
-    let mut value: u8 = 0;
-    for i in 0x134 .. 0x14D {
-        value = value.wrapping_sub(data[i]).wrapping_sub(1);
+    fn set_rumble_callback(&mut self, callback: RumbleCallback) {
+        self.mbc.set_rumble_callback(callback)
     }
-    match data[0x14D] == value
-    {
-        true => Ok(()),
-        false => Err("Cartridge checksum is invalid"),
+
+    fn save(&mut self) -> Result<()> {
+        self.poll_ram_updated();
+        self.write_save().map_err(|e| Error::SaveFile("Could not write the save file", self.rampath.clone().unwrap_or_default(), e))
+    }
+}
+
+
+impl Drop for FileBackedMBC {
+    fn drop(&mut self) {
+        // Also runs while a panic unwinds, so the progress up to the crash is kept. Writing an
+        // unchanged save would still rotate the backups, so only changes are saved.
+        self.poll_ram_updated();
+        if self.dirty {
+            self.save_and_report();
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::{get_mbc, save_file, FileBackedMBC, FileCartridge, MBC, MemoryCartridge, MockCartridge, AUTOSAVE_TICKS};
+    use crate::Error;
+    use std::error::Error as _;
+    use std::fs;
+    use std::io;
+
+    fn rom_only(title: &str) -> Vec<u8> {
+        let mut data = vec![0; 0x8000];
+        data[0x134..0x134 + title.len()].copy_from_slice(title.as_bytes());
+        data
+    }
+
     #[test]
     fn checksum_zero() {
         let mut data = vec![0; 0x150];
         data[0x14D] = -(0x14D_i32 - 0x134_i32) as u8;
-        super::check_checksum(&data).unwrap();
+        assert_eq!(gbcart::header_checksum(&data), data[0x14D]);
     }
 
     #[test]
     fn checksum_ones() {
         let mut data = vec![1; 0x150];
         data[0x14D] = (-(0x14D_i32 - 0x134_i32) * 2) as u8;
-        super::check_checksum(&data).unwrap();
+        assert_eq!(gbcart::header_checksum(&data), data[0x14D]);
+    }
+
+    #[test]
+    fn rejects_unknown_rom_size() {
+        for cartridge_type in [0x01, 0x05, 0x19, 0x22, 0xFC, 0xFD, 0xFE, 0xFF] {
+            let mut rom = rom_only("BANKS");
+            rom[0x147] = cartridge_type;
+            rom[0x148] = 0x20;
+            assert!(matches!(get_mbc(&mut MemoryCartridge::new(rom), true), Err(Error::Rom(_))));
+        }
+    }
+
+    #[test]
+    fn loads_mmm01_with_menu_in_bank_0() {
+        let mut rom = rom_only("MENU");
+        rom[0x147] = 0x0B;
+        let mbc = get_mbc(&mut MemoryCartridge::new(rom), true).unwrap();
+        assert_eq!(mbc.romname(), "MENU");
+    }
+
+    #[test]
+    fn memory_cartridge() {
+        let mbc = get_mbc(&mut MemoryCartridge::new(rom_only("MEMORY")), true).unwrap();
+        assert_eq!(mbc.romname(), "MEMORY");
+    }
+
+    #[test]
+    fn mock_cartridge_follows_script() {
+        let mut source = MockCartridge::new()
+            .then_error("Bad contact")
+            .then_rom(rom_only("MOCK"));
+        assert!(matches!(get_mbc(&mut source, true), Err(Error::Cartridge("Bad contact"))));
+        let mbc = FileBackedMBC::new(&mut source, true).unwrap();
+        assert_eq!(mbc.romname(), "MOCK");
+        assert!(get_mbc(&mut source, true).is_err());
+        assert_eq!(source.reads(), 3);
+    }
+
+    #[test]
+    fn keeps_the_cause_of_errors() {
+        let path = std::env::temp_dir().join(format!("rboy_missing_{}.gb", std::process::id()));
+        let error = get_mbc(&mut FileCartridge::new(&path), true).err().unwrap();
+        assert!(matches!(error, Error::RomFile(ref e) if e.kind() == io::ErrorKind::NotFound));
+        assert!(error.source().is_some());
+
+        let error = Error::from(gbcart::Error::NoCartridge);
+        assert_eq!(error.to_string(), "No cartridge detected or the cartridge header is corrupt");
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn autosaves_changed_ram() {
+        let dir = std::env::temp_dir().join(format!("rboy_autosave_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.gbsave");
+        let mut rom = rom_only("AUTOSAVE");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        fs::write(&path, vec![0x11; 0x2000]).unwrap();
+
+        let mut source = MockCartridge::new().then_rom(rom).with_save_path(&path);
+        let mut mbc = FileBackedMBC::new(&mut source, true).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        mbc.do_cycle(AUTOSAVE_TICKS);
+        // Nothing changed yet, so the save of the previous session is untouched
+        assert!(!save_file::backup_path(&path, 1).exists());
+
+        mbc.writeram(0xA000, 0x22);
+        mbc.do_cycle(AUTOSAVE_TICKS - 1);
+        assert_eq!(fs::read(&path).unwrap()[0], 0x11);
+        mbc.do_cycle(1);
+        assert_eq!(fs::read(&path).unwrap()[0], 0x22);
+        assert_eq!(fs::read(save_file::backup_path(&path, 1)).unwrap()[0], 0x11);
+        assert!(mbc.check_and_reset_ram_updated());
+
+        mbc.writeram(0xA000, 0x33);
+        drop(mbc);
+        assert_eq!(fs::read(&path).unwrap()[0], 0x33);
+        assert!(!save_file::backup_path(&path, 2).exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn keeps_backups_without_changes() {
+        let dir = std::env::temp_dir().join(format!("rboy_keep_backups_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.gbsave");
+        let mut rom = rom_only("BACKUPS");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        fs::write(&path, vec![0x11; 0x2000]).unwrap();
+        fs::write(save_file::backup_path(&path, 1), vec![0x22; 0x2000]).unwrap();
+
+        let mut source = MockCartridge::new().then_rom(rom).with_save_path(&path);
+        let mut mbc = FileBackedMBC::new(&mut source, true).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        assert_eq!(mbc.readram(0xA000), 0x11);
+        mbc.do_cycle(AUTOSAVE_TICKS);
+        drop(mbc);
+        assert_eq!(fs::read(&path).unwrap()[0], 0x11);
+        assert_eq!(fs::read(save_file::backup_path(&path, 1)).unwrap()[0], 0x22);
+        assert!(!save_file::backup_path(&path, 2).exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn migrates_title_based_save() {
+        let dir = std::env::temp_dir().join(format!("rboy_migrate_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let legacy = dir.join("MIGRATE.gbsave");
+        let path = dir.join("saves").join("MIGRATE-12345678.gbsave");
+        fs::write(&legacy, vec![0x44; 0x2000]).unwrap();
+        let mut rom = rom_only("MIGRATE");
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let mut source = MockCartridge::new().then_rom(rom).with_save_path(&path).with_legacy_save_path(&legacy);
+        let mut mbc = FileBackedMBC::new(&mut source, true).unwrap();
+        mbc.writerom(0x0000, 0x0A);
+        assert_eq!(mbc.readram(0xA000), 0x44);
+        assert!(!legacy.exists());
+        assert!(path.exists());
+        drop(mbc);
+
+        let _ = fs::remove_dir_all(dir);
     }
 }
@@ -0,0 +1,228 @@
+//! Conversion between rboy's saves and the raw `.sav`/`.srm` files of other emulators and flash
+//! cartridges, which hold only the save RAM and, for MBC3 clocks, the VBA-M/BGB footer.
+
+use crate::mbc::{huc3, mbc_from_rom, save_file, CartridgeSource, MBC};
+use crate::{Error, Result};
+use gbcart::{RtcFooter, RTC_FOOTER_LEN};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The rboy save a conversion used, and what it had to change on the way
+pub struct SaveReport {
+    pub path: PathBuf,
+    pub notes: Vec<String>,
+}
+
+/// How a cartridge lays its save out in a `.gbsave`
+struct Layout {
+    /// Bytes of clock state in front of the RAM, which only rboy understands
+    prefix: usize,
+    ram: usize,
+    /// The RAM is followed by the VBA-M/BGB clock footer
+    rtc_footer: bool,
+    /// MBC2 RAM, which has only 4 bits in each byte
+    nibbles: bool,
+}
+
+impl Layout {
+    fn of(mbc: &dyn MBC) -> Layout {
+        let cartridge_type = mbc.header().cartridge_type;
+        let prefix = match cartridge_type {
+            0xFD => 8,
+            0xFE => huc3::SAVE_PREFIX,
+            _ => 0,
+        };
+        let rtc_footer = matches!(cartridge_type, 0x0F | 0x10);
+        let footer = if rtc_footer { RTC_FOOTER_LEN } else { 0 };
+        Layout {
+            prefix,
+            ram: mbc.dumpram().len() - prefix - footer,
+            rtc_footer,
+            nibbles: matches!(cartridge_type, 0x05 | 0x06),
+        }
+    }
+}
+
+/// Converts the raw save `file` for the cartridge in `source` and stores it as its save
+pub fn import_save(source: &mut dyn CartridgeSource, file: &Path) -> Result<SaveReport> {
+    let (mut mbc, path) = open(source)?;
+    let raw = fs::read(file).map_err(|e| Error::SaveFile("Could not read", file.to_path_buf(), e))?;
+    let notes = import_raw(&mut *mbc, &raw)?;
+
+    let write = || {
+        save_file::create_parent(&path)?;
+        save_file::rotate_backups(&path)?;
+        save_file::write_atomic(&path, &mbc.dumpram())
+    };
+    write().map_err(|e| Error::SaveFile("Could not save to", path.clone(), e))?;
+    Ok(SaveReport { path, notes })
+}
+
+/// Writes the save of the cartridge in `source` to `file` as a raw save. `rtc` keeps the clock
+/// footer of MBC3 cartridges, which some flash cartridges do not accept.
+pub fn export_save(source: &mut dyn CartridgeSource, file: &Path, rtc: bool) -> Result<SaveReport> {
+    let (mut mbc, path) = open(source)?;
+    let save = match fs::read(&path) {
+        Ok(save) => save,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(Error::NoSave(path)),
+        Err(e) => return Err(Error::SaveFile("Could not read the save", path, e)),
+    };
+    mbc.loadram(&save)?;
+
+    let (raw, notes) = export_raw(&*mbc, rtc);
+    fs::write(file, raw).map_err(|e| Error::SaveFile("Could not write", file.to_path_buf(), e))?;
+    Ok(SaveReport { path, notes })
+}
+
+fn open(source: &mut dyn CartridgeSource) -> Result<(Box<dyn MBC>, PathBuf)> {
+    let data = source.read_rom()?;
+    let path = source.save_path(&data).ok_or(Error::Save("There is no directory to keep the save in"))?;
+    // Converting a save does not depend on an intact ROM
+    let mbc = mbc_from_rom(data, true)?;
+    if !mbc.is_battery_backed() {
+        return Err(Error::Save("The cartridge has no battery backed RAM"));
+    }
+    Ok((mbc, path))
+}
+
+/// Loads a raw save into `mbc`, fitting it to the size of the RAM
+fn import_raw(mbc: &mut dyn MBC, raw: &[u8]) -> Result<Vec<String>> {
+    let layout = Layout::of(mbc);
+    let mut notes = vec![];
+
+    let (data, footer) = match RtcFooter::split(raw, layout.ram) {
+        Some((data, footer)) if layout.rtc_footer => (data, Some(footer)),
+        _ => (raw, None),
+    };
+    if layout.rtc_footer && footer.is_none() {
+        notes.push("The save has no clock footer, so the clock keeps its current time".to_string());
+    }
+
+    let mut ram = data.to_vec();
+    if ram.len() > layout.ram {
+        let extra = ram.split_off(layout.ram);
+        if is_padding(&extra, &ram) {
+            notes.push(format!("Ignored {} bytes of padding behind the RAM", extra.len()));
+        } else {
+            notes.push(format!(
+                "Ignored {} bytes behind the {} bytes of RAM which are not padding, check that the save belongs to this game",
+                extra.len(), layout.ram));
+        }
+    } else if ram.len() < layout.ram {
+        notes.push(format!(
+            "The save has only {} of {} bytes of RAM, the rest is left blank",
+            ram.len(), layout.ram));
+        ram.resize(layout.ram, 0xFF);
+    }
+    if layout.nibbles {
+        if ram.iter().any(|v| v & 0xF0 != 0xF0 && v & 0xF0 != 0) {
+            notes.push("Kept only the lower 4 bits of each byte, as MBC2 RAM has no more".to_string());
+        }
+        for v in ram.iter_mut() {
+            *v |= 0xF0;
+        }
+    }
+
+    // The clock state in front of the RAM stays as the cartridge has it now
+    let mut save = mbc.dumpram()[..layout.prefix].to_vec();
+    save.extend_from_slice(&ram);
+    if let Some(footer) = footer {
+        save.extend_from_slice(&footer.to_bytes());
+    }
+    mbc.loadram(&save)?;
+    Ok(notes)
+}
+
+/// The raw save of `mbc`, with the clock footer of MBC3 cartridges if `rtc` is set
+fn export_raw(mbc: &dyn MBC, rtc: bool) -> (Vec<u8>, Vec<String>) {
+    let layout = Layout::of(mbc);
+    let save = mbc.dumpram();
+    let mut notes = vec![];
+    if layout.prefix > 0 {
+        notes.push("Left out the clock, which other emulators keep in their own format".to_string());
+    }
+    let end = if layout.rtc_footer && !rtc { save.len() - RTC_FOOTER_LEN } else { save.len() };
+    if layout.rtc_footer && rtc {
+        notes.push(format!("Wrote the clock as a {} byte footer behind the RAM", RTC_FOOTER_LEN));
+    }
+    (save[layout.prefix..end].to_vec(), notes)
+}
+
+/// Whether `extra` holds nothing of value: blank bytes, or mirrors of `ram` as some dumpers write
+fn is_padding(extra: &[u8], ram: &[u8]) -> bool {
+    extra.iter().all(|&v| v == 0x00) || extra.iter().all(|&v| v == 0xFF)
+        || (!ram.is_empty() && extra.chunks(ram.len()).all(|chunk| chunk == &ram[..chunk.len()]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{export_raw, export_save, import_raw, import_save};
+    use crate::mbc::{mbc_from_rom, MockCartridge, MBC};
+    use crate::Error;
+
+    fn cartridge(cartridge_type: u8, ram_size: u8) -> Box<dyn MBC> {
+        let mut data = vec![0; 0x8000];
+        data[0x147] = cartridge_type;
+        data[0x149] = ram_size;
+        mbc_from_rom(data, true).unwrap()
+    }
+
+    #[test]
+    fn fits_raw_saves_to_the_ram() {
+        // MBC1 with 8 KiB of RAM, from a dumper which pads to 32 KiB with mirrors
+        let mut mbc = cartridge(0x03, 0x02);
+        let ram: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+        let padded = ram.repeat(4);
+        let notes = import_raw(&mut *mbc, &padded).unwrap();
+        assert_eq!(notes, ["Ignored 24576 bytes of padding behind the RAM"]);
+        assert_eq!(mbc.dumpram(), ram);
+
+        let notes = import_raw(&mut *mbc, &ram[..0x800]).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(&mbc.dumpram()[..0x800], &ram[..0x800]);
+        assert!(mbc.dumpram()[0x800..].iter().all(|&v| v == 0xFF));
+
+        // MBC2 keeps 4 bits, however another emulator stored the upper ones
+        let mut mbc = cartridge(0x06, 0x00);
+        let notes = import_raw(&mut *mbc, &[0x0A; 512]).unwrap();
+        assert!(notes.is_empty());
+        assert_eq!(mbc.dumpram(), [0xFA; 512]);
+        assert_eq!(export_raw(&*mbc, true).0, [0xFA; 512]);
+    }
+
+    #[test]
+    fn keeps_the_rtc_footer() {
+        let mut mbc = cartridge(0x10, 0x02);
+        let mut raw = vec![0x42; 0x2000];
+        let footer = gbcart::RtcFooter { registers: [1, 2, 3, 4, 0x40], latched: [1, 2, 3, 4, 0x40], timestamp: 1 };
+        raw.extend_from_slice(&footer.to_bytes()[..44]);
+        assert!(import_raw(&mut *mbc, &raw).unwrap().is_empty());
+
+        let (exported, _) = export_raw(&*mbc, true);
+        assert_eq!(&exported[..0x2000], &raw[..0x2000]);
+        // The clock is halted, so it still reads as imported
+        assert_eq!(gbcart::RtcFooter::from_bytes(&exported[0x2000..]).unwrap().registers, footer.registers);
+        assert_eq!(export_raw(&*mbc, false).0.len(), 0x2000);
+
+        let notes = import_raw(&mut *mbc, &raw[..0x2000]).unwrap();
+        assert_eq!(notes.len(), 1);
+    }
+
+    #[test]
+    fn reports_typed_errors() {
+        let dir = std::env::temp_dir().join(format!("rboy_convert_{}", std::process::id()));
+        let path = dir.join("game.gbsave");
+        let raw = dir.join("game.sav");
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+
+        let mut source = MockCartridge::new().then_rom(rom.clone()).with_save_path(&path);
+        assert!(matches!(export_save(&mut source, &raw, true), Err(Error::NoSave(ref p)) if *p == path));
+        let mut source = MockCartridge::new().then_rom(rom).with_save_path(&path);
+        assert!(matches!(import_save(&mut source, &raw), Err(Error::SaveFile(_, ref p, _)) if *p == raw));
+        let mut source = MockCartridge::new().then_rom(vec![0; 0x8000]).with_save_path(&path);
+        assert!(matches!(import_save(&mut source, &raw), Err(Error::Save(_))));
+    }
+}
@@ -0,0 +1,145 @@
+//! Location and writing of battery saves, such that a crash at any point leaves a complete save
+//! behind.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Number of older saves kept next to the save, as `.bak1` (the newest) to `.bakN`
+pub const BACKUPS: usize = 3;
+
+/// The default directory for saves, `$XDG_DATA_HOME/rboy/saves` on Linux
+pub fn default_dir() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("rboy").join("saves"))
+}
+
+/// File name of the save for `rom`. The CRC32 of the ROM tells games with the same name apart, and
+/// `label`, the title or the name of the ROM file, keeps it readable.
+pub fn save_name(label: &str, rom: &[u8]) -> String {
+    let label: String = label
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let label = match label.trim_matches('_') {
+        "" => "untitled",
+        label => label,
+    };
+    format!("{}-{:08x}.gbsave", label, gbcart::RomHashes::of(rom).crc32)
+}
+
+/// The save for `rom` in `dir`, or in the default directory
+pub fn save_path(dir: Option<&Path>, label: &str, rom: &[u8]) -> Option<PathBuf> {
+    let dir = dir.map(PathBuf::from).or_else(default_dir)?;
+    Some(dir.join(save_name(label, rom)))
+}
+
+/// Where older versions kept the save: named after the title, in the working directory
+pub fn legacy_save_path(romname: &str) -> Option<PathBuf> {
+    if romname.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(romname).with_extension("gbsave"))
+}
+
+/// Moves a save from where an older version kept it to `path`
+pub fn migrate(legacy: &Path, path: &Path) -> io::Result<()> {
+    create_parent(path)?;
+    if fs::rename(legacy, path).is_err() {
+        // Renaming does not work across filesystems
+        fs::copy(legacy, path)?;
+        fs::remove_file(legacy)?;
+    }
+    Ok(())
+}
+
+pub fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => fs::create_dir_all(dir),
+        _ => Ok(()),
+    }
+}
+
+/// `path` with `suffix` appended to the file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+pub fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    with_suffix(path, &format!(".bak{}", generation))
+}
+
+/// Replaces the file at `path` with `data`. The data is written to a temporary file first and
+/// renamed over the old save, so the save is always either the old or the new one.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path = with_suffix(path, ".tmp");
+    let result = (|| {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+    // Make the rename itself durable. Not every platform can open a directory, which is fine.
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        if let Ok(dir) = fs::File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    Ok(())
+}
+
+/// Shifts the backups of `path` by one generation and copies the current save to `.bak1`
+pub fn rotate_backups(path: &Path) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    for generation in (1..BACKUPS).rev() {
+        let older = backup_path(path, generation);
+        if older.exists() {
+            fs::rename(&older, backup_path(path, generation + 1))?;
+        }
+    }
+    // A copy, so that the save itself stays in place
+    fs::copy(path, backup_path(path, 1)).map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{backup_path, rotate_backups, save_name, write_atomic, BACKUPS};
+    use std::fs;
+
+    #[test]
+    fn keeps_rolling_backups() {
+        let dir = std::env::temp_dir().join(format!("rboy_save_file_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.gbsave");
+
+        for generation in 0..5u8 {
+            rotate_backups(&path).unwrap();
+            write_atomic(&path, &[generation; 4]).unwrap();
+        }
+        assert_eq!(fs::read(&path).unwrap(), [4; 4]);
+        for generation in 1..=BACKUPS {
+            assert_eq!(fs::read(backup_path(&path, generation)).unwrap(), [4 - generation as u8; 4]);
+        }
+        assert!(!backup_path(&path, BACKUPS + 1).exists());
+        assert!(!dir.join("game.gbsave.tmp").exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn names_saves_by_rom() {
+        let (red, blue) = (vec![1; 0x8000], vec![2; 0x8000]);
+        assert_ne!(save_name("POKEMON RED", &red), save_name("POKEMON RED", &blue));
+        assert!(save_name("POKEMON RED", &red).starts_with("POKEMON_RED-"));
+        assert!(save_name("../..", &red).starts_with("untitled-"));
+        assert!(save_name("", &red).ends_with(".gbsave"));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::mbc::save_file;
+use crate::{Error, Result};
+use gbcart::CartridgeHeader;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+/// Provides the ROM image of a cartridge to `get_mbc` and `FileBackedMBC`
+pub trait CartridgeSource {
+    fn read_rom(&mut self) -> Result<Vec<u8>>;
+
+    /// Location of the save file for battery backed RAM, or `None` if the RAM should not be persisted
+    fn save_path(&self, rom: &[u8]) -> Option<PathBuf> {
+        save_file::save_path(None, &title(rom), rom)
+    }
+
+    /// Where older versions kept the save, which is moved to `save_path` if nothing is there yet
+    fn legacy_save_path(&self, romname: &str) -> Option<PathBuf> {
+        save_file::legacy_save_path(romname)
+    }
+}
+
+fn title(rom: &[u8]) -> String {
+    CartridgeHeader::from_bytes(rom).map(|header| header.title).unwrap_or_default()
+}
+
+/// A ROM image stored on the filesystem
+pub struct FileCartridge {
+    path: PathBuf,
+    save_dir: Option<PathBuf>,
+}
+
+impl FileCartridge {
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileCartridge {
+        FileCartridge { path: path.into(), save_dir: None }
+    }
+
+    /// Keeps the save in `dir` instead of the default save directory
+    pub fn with_save_dir<P: Into<PathBuf>>(mut self, dir: P) -> FileCartridge {
+        self.save_dir = Some(dir.into());
+        self
+    }
+}
+
+impl CartridgeSource for FileCartridge {
+    fn read_rom(&mut self) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        match File::open(&self.path).and_then(|mut f| f.read_to_end(&mut data)) {
+            Ok(..) => Ok(data),
+            Err(e) => Err(Error::RomFile(e)),
+        }
+    }
+
+    /// Named after the ROM file, so the save is easy to find
+    fn save_path(&self, rom: &[u8]) -> Option<PathBuf> {
+        let label = self.path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        save_file::save_path(self.save_dir.as_deref(), &label, rom)
+    }
+}
+
+/// A ROM image that is already in memory. Its RAM is never persisted.
+pub struct MemoryCartridge {
+    data: Vec<u8>,
+}
+
+impl MemoryCartridge {
+    pub fn new(data: Vec<u8>) -> MemoryCartridge {
+        MemoryCartridge { data }
+    }
+}
+
+impl CartridgeSource for MemoryCartridge {
+    fn read_rom(&mut self) -> Result<Vec<u8>> {
+        Ok(self.data.clone())
+    }
+
+    fn save_path(&self, _rom: &[u8]) -> Option<PathBuf> {
+        None
+    }
+
+    fn legacy_save_path(&self, _romname: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Wraps another source and reports whether its ROM matches an entry of a DAT file
+pub struct DatCheckedCartridge<'a> {
+    source: &'a mut dyn CartridgeSource,
+    dat: &'a gbcart::Dat,
+}
+
+impl<'a> DatCheckedCartridge<'a> {
+    pub fn new(source: &'a mut dyn CartridgeSource, dat: &'a gbcart::Dat) -> DatCheckedCartridge<'a> {
+        DatCheckedCartridge { source, dat }
+    }
+}
+
+impl<'a> CartridgeSource for DatCheckedCartridge<'a> {
+    fn read_rom(&mut self) -> Result<Vec<u8>> {
+        let data = self.source.read_rom()?;
+        println!("{}", self.dat.lookup(&data));
+        Ok(data)
+    }
+
+    fn save_path(&self, rom: &[u8]) -> Option<PathBuf> {
+        self.source.save_path(rom)
+    }
+
+    fn legacy_save_path(&self, romname: &str) -> Option<PathBuf> {
+        self.source.legacy_save_path(romname)
+    }
+}
+
+/// A source which replays a fixed script of results, one per call to `read_rom`
+pub struct MockCartridge {
+    script: VecDeque<Result<Vec<u8>>>,
+    save_path: Option<PathBuf>,
+    legacy_save_path: Option<PathBuf>,
+    reads: usize,
+}
+
+impl MockCartridge {
+    pub fn new() -> MockCartridge {
+        MockCartridge {
+            script: VecDeque::new(),
+            save_path: None,
+            legacy_save_path: None,
+            reads: 0,
+        }
+    }
+
+    pub fn then_rom(mut self, data: Vec<u8>) -> MockCartridge {
+        self.script.push_back(Ok(data));
+        self
+    }
+
+    pub fn then_error(mut self, message: &'static str) -> MockCartridge {
+        self.script.push_back(Err(Error::Cartridge(message)));
+        self
+    }
+
+    pub fn with_save_path<P: Into<PathBuf>>(mut self, path: P) -> MockCartridge {
+        self.save_path = Some(path.into());
+        self
+    }
+
+    pub fn with_legacy_save_path<P: Into<PathBuf>>(mut self, path: P) -> MockCartridge {
+        self.legacy_save_path = Some(path.into());
+        self
+    }
+
+    /// Number of times `read_rom` has been called
+    pub fn reads(&self) -> usize {
+        self.reads
+    }
+}
+
+impl Default for MockCartridge {
+    fn default() -> MockCartridge {
+        MockCartridge::new()
+    }
+}
+
+impl CartridgeSource for MockCartridge {
+    fn read_rom(&mut self) -> Result<Vec<u8>> {
+        self.reads += 1;
+        match self.script.pop_front() {
+            Some(result) => result,
+            None => Err(Error::Cartridge("Mock cartridge has no scripted reads left")),
+        }
+    }
+
+    fn save_path(&self, _rom: &[u8]) -> Option<PathBuf> {
+        self.save_path.clone()
+    }
+
+    fn legacy_save_path(&self, _romname: &str) -> Option<PathBuf> {
+        self.legacy_save_path.clone()
+    }
+}
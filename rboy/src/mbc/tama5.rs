@@ -0,0 +1,280 @@
+use crate::mbc::MBC;
+use crate::rtc::now;
+use crate::{Error, Result};
+
+use std::convert::TryInto;
+
+/// Bytes of memory which the TAMA6 microcontroller keeps for the game
+const RAM_SIZE: usize = 0x20;
+/// Unix time of 2000-01-01, where the two digit years of the clock start
+const Y2K: u64 = 946684800;
+
+/// The TAMA5 mapper of Tamagotchi 3, which the game talks to through a register at 0xA001 that
+/// selects one of 16 four bit registers, and a data port at 0xA000
+pub struct TAMA5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rombank: usize,
+    rombanks: usize,
+    selected: u8,
+    registers: [u8; 0x10],
+    /// Unix time at which the clock showed 2000-01-01 00:00:00
+    rtc_zero: u64,
+    ram_updated: bool,
+}
+
+impl TAMA5 {
+    pub fn new(data: Vec<u8>) -> Result<TAMA5> {
+        let rombanks = (data.len() / 0x4000).max(1);
+
+        let res = TAMA5 {
+            rom: data,
+            ram: vec![0; RAM_SIZE],
+            rombank: 1,
+            rombanks,
+            selected: 0,
+            registers: [0; 0x10],
+            rtc_zero: Y2K,
+            ram_updated: false,
+        };
+
+        Ok(res)
+    }
+
+    /// Runs the memory or clock operation in register 6 on the address in registers 6 and 7
+    fn execute(&mut self) {
+        let address = (((self.registers[6] & 0x01) << 4) | self.registers[7]) as usize;
+        let data = (self.registers[5] << 4) | self.registers[4];
+        let result = match self.registers[6] >> 1 {
+            0x0 => {
+                self.ram[address] = data;
+                self.ram_updated = true;
+                return;
+            },
+            0x1 => self.ram[address],
+            // The TC8521 clock, one decimal digit per register
+            0x2 => {
+                let mut digits = Clock::at(self.seconds()).digits();
+                if let Some(digit) = digits.get_mut(address & 0x0F) {
+                    *digit = data & 0x0F;
+                }
+                self.rtc_zero = now().wrapping_sub(Clock::from_digits(&digits).seconds());
+                self.ram_updated = true;
+                return;
+            },
+            0x3 => Clock::at(self.seconds()).digits().get(address & 0x0F).copied().unwrap_or(0),
+            _ => return,
+        };
+        self.registers[0xC] = result & 0x0F;
+        self.registers[0xD] = result >> 4;
+    }
+
+    /// Seconds since 2000-01-01 on the clock of the cartridge
+    fn seconds(&self) -> u64 {
+        now().wrapping_sub(self.rtc_zero)
+    }
+}
+
+impl MBC for TAMA5 {
+    fn readrom(&self, a: u16) -> u8 {
+        let idx = if a < 0x4000 { a as usize }
+        else { (self.rombank * 0x4000) | ((a as usize) & 0x3FFF) };
+        *self.rom.get(idx).unwrap_or(&0xFF)
+    }
+
+    fn readram(&self, a: u16) -> u8 {
+        if a & 0x01 != 0 { return 0xFF }
+        match self.selected {
+            // Always ready for the next command
+            0xA => 0xF1,
+            0xC | 0xD => 0xF0 | self.registers[self.selected as usize],
+            _ => 0xFF,
+        }
+    }
+
+    fn writerom(&mut self, _a: u16, _v: u8) {
+        // All registers are behind 0xA000-0xA001
+    }
+
+    fn writeram(&mut self, a: u16, v: u8) {
+        if a & 0x01 != 0 {
+            self.selected = v & 0x0F;
+            return;
+        }
+        let register = self.selected as usize;
+        self.registers[register] = v & 0x0F;
+        match register {
+            0x0 | 0x1 => {
+                self.rombank = (((self.registers[1] & 0x01) << 4) | self.registers[0]) as usize % self.rombanks;
+            },
+            0x7 => self.execute(),
+            _ => {},
+        }
+    }
+
+    fn is_battery_backed(&self) -> bool {
+        true
+    }
+
+    fn loadram(&mut self, ramdata: &[u8]) -> Result<()> {
+        if ramdata.len() != 8 + RAM_SIZE {
+            return Err(Error::Save("Loaded ram is too small"));
+        }
+
+        let (int_bytes, rest) = ramdata.split_at(8);
+        self.rtc_zero = u64::from_be_bytes(int_bytes.try_into().unwrap());
+        self.ram = rest.to_vec();
+        Ok(())
+    }
+
+    fn dumpram(&self) -> Vec<u8> {
+        let mut file = self.rtc_zero.to_be_bytes().to_vec();
+        file.extend_from_slice(&self.ram);
+        file
+    }
+
+    fn check_and_reset_ram_updated(&mut self) -> bool {
+        let result = self.ram_updated;
+        self.ram_updated = false;
+        result
+    }
+}
+
+/// A date and time between 2000 and 2099
+#[derive(Debug, PartialEq)]
+struct Clock {
+    year: u64,
+    month: u64,
+    day: u64,
+    weekday: u64,
+    hour: u64,
+    minute: u64,
+    second: u64,
+}
+
+impl Clock {
+    fn at(seconds: u64) -> Clock {
+        let days = seconds / 86400;
+        let (year, month, day) = civil_from_days(days % days_from_civil(100, 1, 1));
+        Clock {
+            year,
+            month,
+            day,
+            // 2000-01-01 was a Saturday
+            weekday: (days + 6) % 7,
+            hour: seconds / 3600 % 24,
+            minute: seconds / 60 % 60,
+            second: seconds % 60,
+        }
+    }
+
+    fn seconds(&self) -> u64 {
+        days_from_civil(self.year, self.month, self.day) * 86400 + self.hour * 3600 + self.minute * 60 + self.second
+    }
+
+    /// The registers of the TC8521, from the seconds to the tens of the year
+    fn digits(&self) -> [u8; 13] {
+        let values = [self.second, self.minute, self.hour];
+        let date = [self.day, self.month, self.year];
+        let mut digits = [0; 13];
+        for (i, value) in values.iter().enumerate() {
+            digits[i * 2] = (value % 10) as u8;
+            digits[i * 2 + 1] = (value / 10) as u8;
+        }
+        digits[6] = self.weekday as u8;
+        for (i, value) in date.iter().enumerate() {
+            digits[7 + i * 2] = (value % 10) as u8;
+            digits[8 + i * 2] = (value / 10) as u8;
+        }
+        digits
+    }
+
+    fn from_digits(digits: &[u8; 13]) -> Clock {
+        let value = |i: usize| (digits[i] + digits[i + 1] * 10) as u64;
+        Clock {
+            second: value(0).min(59),
+            minute: value(2).min(59),
+            hour: value(4).min(23),
+            weekday: digits[6] as u64,
+            day: value(7).clamp(1, 31),
+            month: value(9).clamp(1, 12),
+            year: value(11),
+        }
+    }
+}
+
+/// Days since 2000-01-01 of a date, with the year counted from 2000
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    // Count years from March, so the leap day is at the end of the year
+    let year = 2000 + year - (month <= 2) as u64;
+    let era_day = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let year_day = era_day + 365 * year + year / 4 - year / 100 + year / 400;
+    // 730425 is 2000-01-01 counted the same way
+    year_day - 730425
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    // Split into 400 year cycles starting at 0000-03-01, the inverse of `days_from_civil`
+    let days = days + 730425;
+    let (era, era_day) = (days / 146097, days % 146097);
+    let year_of_era = (era_day - era_day / 1460 + era_day / 36524 - era_day / 146096) / 365;
+    let day_of_year = era_day - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + (month <= 2) as u64 - 2000;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Clock, TAMA5};
+    use crate::mbc::MBC;
+
+    fn write(mbc: &mut TAMA5, register: u8, value: u8) {
+        mbc.writeram(0xA001, register);
+        mbc.writeram(0xA000, value);
+    }
+
+    fn read(mbc: &mut TAMA5, register: u8) -> u8 {
+        mbc.writeram(0xA001, register);
+        mbc.readram(0xA000) & 0x0F
+    }
+
+    #[test]
+    fn converts_dates() {
+        // 2024-02-29 12:34:56, a Thursday
+        let clock = Clock::at(8825 * 86400 + 12 * 3600 + 34 * 60 + 56);
+        assert_eq!((clock.year, clock.month, clock.day, clock.weekday), (24, 2, 29, 4));
+        assert_eq!(clock.digits(), [6, 5, 4, 3, 2, 1, 4, 9, 2, 2, 0, 4, 2]);
+        assert_eq!(Clock::from_digits(&clock.digits()), clock);
+        assert_eq!(Clock::at(0).seconds(), 0);
+    }
+
+    #[test]
+    fn memory_and_clock_commands() {
+        let mut mbc = TAMA5::new(vec![0; 0x40000]).unwrap();
+        write(&mut mbc, 0x4, 0x5);
+        write(&mut mbc, 0x5, 0xA);
+        write(&mut mbc, 0x6, 0x1);
+        write(&mut mbc, 0x7, 0x3);
+        assert_eq!(mbc.dumpram()[8 + 0x13], 0xA5);
+
+        write(&mut mbc, 0x6, 0x3);
+        write(&mut mbc, 0x7, 0x3);
+        assert_eq!((read(&mut mbc, 0xC), read(&mut mbc, 0xD)), (0x5, 0xA));
+
+        // Set the tens of the year to 9, then read them back
+        write(&mut mbc, 0x4, 0x9);
+        write(&mut mbc, 0x6, 0x4);
+        write(&mut mbc, 0x7, 0xC);
+        write(&mut mbc, 0x6, 0x6);
+        write(&mut mbc, 0x7, 0xC);
+        assert_eq!(read(&mut mbc, 0xC), 0x9);
+        assert_eq!(read(&mut mbc, 0xA), 0x1);
+
+        write(&mut mbc, 0x0, 0x2);
+        write(&mut mbc, 0x1, 0x1);
+        assert_eq!(mbc.rombank, 0x12 % 0x10);
+    }
+}
@@ -4,7 +4,7 @@ use crate::keypad::Keypad;
 use crate::gpu::GPU;
 use crate::sound::Sound;
 use crate::gbmode::{GbMode, GbSpeed};
-use crate::StrResult;
+use crate::{Error, Result};
 use crate::mbc;
 
 const WRAM_SIZE: usize = 0x8000;
@@ -38,6 +38,8 @@ pub struct MMU<'a> {
     gbspeed: GbSpeed,
     speed_switch_req: bool,
     undocumented_cgb_regs: [u8; 3],  // 0xFF72, 0xFF73, 0xFF75
+    /// An error of the game, reported after the current instruction
+    fault: Option<Error>,
 }
 
 fn fill_random(slice: &mut [u8], start: u32) {
@@ -54,7 +56,7 @@ fn fill_random(slice: &mut [u8], start: u32) {
 }
 
 impl<'a> MMU<'a> {
-    pub fn new(cart: Box<dyn mbc::MBC+'static>, serial_callback: Option<SerialCallback<'a>>) -> StrResult<MMU<'a>> {
+    pub fn new(cart: Box<dyn mbc::MBC+'static>, serial_callback: Option<SerialCallback<'a>>) -> Result<MMU<'a>> {
         let serial = match serial_callback {
             Some(cb) => Serial::new_with_callback(cb),
             None => Serial::new(),
@@ -80,16 +82,17 @@ impl<'a> MMU<'a> {
             hdma_status: DMAType::NoDMA,
             hdma_len: 0xFF,
             undocumented_cgb_regs: [0; 3],
+            fault: None,
         };
         fill_random(&mut res.wram, 42);
         if res.rb(0x0143) == 0xC0 {
-            return Err("This game does not work in Classic mode");
+            return Err(Error::RequiresColor);
         }
         res.set_initial();
         Ok(res)
     }
 
-    pub fn new_cgb(cart: Box<dyn mbc::MBC+'static>, serial_callback: Option<SerialCallback<'a>>) -> StrResult<MMU<'a>> {
+    pub fn new_cgb(cart: Box<dyn mbc::MBC+'static>, serial_callback: Option<SerialCallback<'a>>) -> Result<MMU<'a>> {
         let serial = match serial_callback {
             Some(cb) => Serial::new_with_callback(cb),
             None => Serial::new(),
@@ -115,6 +118,7 @@ impl<'a> MMU<'a> {
             hdma_status: DMAType::NoDMA,
             hdma_len: 0xFF,
             undocumented_cgb_regs: [0; 3],
+            fault: None,
         };
         fill_random(&mut res.wram, 42);
         res.determine_mode();
@@ -184,12 +188,24 @@ impl<'a> MMU<'a> {
 
         let _ = self.sound.as_mut().map_or((), |s| s.do_cycle(gputicks));
 
+        self.mbc.do_cycle(gputicks);
+
         self.intf |= self.serial.interrupt;
         self.serial.interrupt = 0;
 
         return gputicks;
     }
 
+    /// Takes the error which the game ran into since the last call, if any
+    pub fn take_fault(&mut self) -> Option<Error> {
+        self.fault.take()
+    }
+
+    /// Stops the game with `error` after the current instruction
+    pub fn report_fault(&mut self, error: Error) {
+        self.fault = Some(error);
+    }
+
     pub fn rb(&mut self, address: u16) -> u8 {
         match address {
             0x0000 ..= 0x7FFF => self.mbc.readrom(address),
@@ -220,7 +236,7 @@ impl<'a> MMU<'a> {
     }
 
     pub fn rw(&mut self, address: u16) -> u16 {
-        (self.rb(address) as u16) | ((self.rb(address + 1) as u16) << 8)
+        (self.rb(address) as u16) | ((self.rb(address.wrapping_add(1)) as u16) << 8)
     }
 
     pub fn wb(&mut self, address: u16, value: u8) {
@@ -254,7 +270,7 @@ impl<'a> MMU<'a> {
 
     pub fn ww(&mut self, address: u16, value: u16) {
         self.wb(address, (value & 0xFF) as u8);
-        self.wb(address + 1, (value >> 8) as u8);
+        self.wb(address.wrapping_add(1), (value >> 8) as u8);
     }
 
     pub fn switch_speed(&mut self) {
@@ -297,7 +313,10 @@ impl<'a> MMU<'a> {
                 }
                 let src = ((self.hdma[0] as u16) << 8) | (self.hdma[1] as u16);
                 let dst = ((self.hdma[2] as u16) << 8) | (self.hdma[3] as u16) | 0x8000;
-                if !(src <= 0x7FF0 || (src >= 0xA000 && src <= 0xDFF0)) { panic!("HDMA transfer with illegal start address {:04X}", src); }
+                if !(src <= 0x7FF0 || (src >= 0xA000 && src <= 0xDFF0)) {
+                    self.report_fault(Error::IllegalHdmaSource(src));
+                    return;
+                }
 
                 self.hdma_src = src;
                 self.hdma_dst = dst;
@@ -347,7 +366,8 @@ impl<'a> MMU<'a> {
             self.gpu.wb(self.hdma_dst + j, b);
         }
         self.hdma_src += 0x10;
-        self.hdma_dst += 0x10;
+        // The destination wraps around within VRAM
+        self.hdma_dst = 0x8000 | ((self.hdma_dst + 0x10) & 0x1FF0);
 
         if self.hdma_len == 0 {
             self.hdma_len = 0x7F;
@@ -0,0 +1,226 @@
+//! The real time clock of MBC3 cartridges.
+
+use gbcart::RtcFooter;
+use std::time;
+
+/// Ticks of the 4 MiHz clock in one second, which does not change in double speed mode
+const TICKS_PER_SECOND: u32 = 4194304;
+/// Bits of S, M, H, DL and DH which exist in the hardware
+const REGISTER_MASKS: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+const DH_DAY: u8 = 0x01;
+const DH_HALT: u8 = 0x40;
+const DH_CARRY: u8 = 0x80;
+
+/// Where the clock takes the passing time from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RtcMode {
+    /// Follows the time of the host, also while the emulator is not running
+    WallClock,
+    /// Counts the cycles of the emulated CPU, so that every run sees the same times
+    Emulated,
+}
+
+/// The clock registers and where their time comes from
+#[derive(Clone)]
+pub(crate) struct Rtc {
+    mode: RtcMode,
+    /// S, M, H, DL and DH
+    registers: [u8; 5],
+    latched: [u8; 5],
+    /// Ticks since the seconds last counted up, in emulated mode
+    ticks: u32,
+    /// Unix time up to which the registers have counted, in wall clock mode
+    synced: u64,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            mode: RtcMode::WallClock,
+            registers: [0; 5],
+            latched: [0; 5],
+            ticks: 0,
+            synced: now(),
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: RtcMode) {
+        self.sync();
+        self.mode = mode;
+        self.synced = now();
+    }
+
+    pub fn do_cycle(&mut self, ticks: u32) {
+        if self.mode != RtcMode::Emulated || self.is_halted() { return }
+        self.ticks += ticks;
+        while self.ticks >= TICKS_PER_SECOND {
+            self.ticks -= TICKS_PER_SECOND;
+            self.tick();
+        }
+    }
+
+    pub fn latch(&mut self) {
+        self.sync();
+        self.latched = self.registers;
+    }
+
+    /// Reads one of the latched registers, 0 to 4 for S, M, H, DL and DH
+    pub fn read(&self, register: usize) -> u8 {
+        self.latched[register]
+    }
+
+    pub fn write(&mut self, register: usize, v: u8) {
+        self.sync();
+        if register == 0 {
+            // Writing the seconds restarts the current second
+            self.ticks = 0;
+        }
+        self.registers[register] = v & REGISTER_MASKS[register];
+    }
+
+    /// Sets the clock to `seconds` after day 0, keeping the halt flag
+    pub fn set(&mut self, seconds: u64) {
+        self.sync();
+        let halt = self.registers[4] & DH_HALT;
+        self.registers = gbcart::advance_rtc([0; 5], seconds);
+        self.registers[4] |= halt;
+        self.ticks = 0;
+    }
+
+    /// Runs the clock for `seconds`, unless it is halted
+    pub fn advance(&mut self, seconds: u64) {
+        self.sync();
+        self.run(seconds);
+    }
+
+    /// The footer for a save, with the registers as they are now
+    pub fn footer(&self) -> RtcFooter {
+        let mut rtc = self.clone();
+        rtc.sync();
+        RtcFooter { registers: rtc.registers, latched: rtc.latched, timestamp: now() }
+    }
+
+    /// Continues from a saved footer. In wall clock mode, the clock catches up with the time since
+    /// the save was written.
+    pub fn load_footer(&mut self, footer: &RtcFooter) {
+        self.registers = mask(footer.registers);
+        self.latched = mask(footer.latched);
+        self.ticks = 0;
+        self.synced = footer.timestamp;
+    }
+
+    /// Continues from the `rtc_zero` of older saves, the unix time at which the clock was at zero
+    pub fn load_rtc_zero(&mut self, rtc_zero: u64) {
+        self.registers = [0; 5];
+        self.ticks = 0;
+        self.synced = rtc_zero;
+    }
+
+    fn is_halted(&self) -> bool {
+        self.registers[4] & DH_HALT != 0
+    }
+
+    /// Catches up with the time of the host in wall clock mode
+    fn sync(&mut self) {
+        if self.mode != RtcMode::WallClock { return }
+        let now = now();
+        self.run(now.saturating_sub(self.synced));
+        self.synced = now;
+    }
+
+    fn run(&mut self, mut seconds: u64) {
+        if self.is_halted() { return }
+        // Out of range values count up to the limit of their bits first, without a carry
+        while seconds > 0 && !(self.registers[0] < 60 && self.registers[1] < 60 && self.registers[2] < 24) {
+            self.tick();
+            seconds -= 1;
+        }
+        if seconds > 0 {
+            self.registers = gbcart::advance_rtc(self.registers, seconds);
+        }
+    }
+
+    fn tick(&mut self) {
+        let registers = &mut self.registers;
+        registers[0] = (registers[0] + 1) & REGISTER_MASKS[0];
+        if registers[0] != 60 { return }
+        registers[0] = 0;
+        registers[1] = (registers[1] + 1) & REGISTER_MASKS[1];
+        if registers[1] != 60 { return }
+        registers[1] = 0;
+        registers[2] = (registers[2] + 1) & REGISTER_MASKS[2];
+        if registers[2] != 24 { return }
+        registers[2] = 0;
+        let days = ((((registers[4] & DH_DAY) as u16) << 8) | registers[3] as u16) + 1;
+        registers[3] = days as u8;
+        registers[4] = (registers[4] & !DH_DAY) | ((days >> 8) as u8 & DH_DAY);
+        if days == 512 {
+            registers[4] |= DH_CARRY;
+        }
+    }
+}
+
+fn mask(registers: [u8; 5]) -> [u8; 5] {
+    let mut masked = registers;
+    for (register, mask) in masked.iter_mut().zip(REGISTER_MASKS) {
+        *register &= mask;
+    }
+    masked
+}
+
+/// The current unix time, or 0 when the host clock is set before 1970
+pub fn now() -> u64 {
+    time::SystemTime::now().duration_since(time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rtc, RtcMode, TICKS_PER_SECOND};
+
+    fn emulated(registers: [u8; 5]) -> Rtc {
+        let mut rtc = Rtc::new();
+        rtc.set_mode(RtcMode::Emulated);
+        for (i, v) in registers.iter().enumerate() {
+            rtc.write(i, *v);
+        }
+        rtc
+    }
+
+    fn latched(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.latch();
+        [0, 1, 2, 3, 4].map(|i| rtc.read(i))
+    }
+
+    #[test]
+    fn counts_emulated_cycles() {
+        let mut rtc = emulated([58, 59, 23, 0xFF, 0x01]);
+        rtc.do_cycle(TICKS_PER_SECOND - 1);
+        assert_eq!(latched(&mut rtc), [58, 59, 23, 0xFF, 0x01]);
+        rtc.do_cycle(1);
+        assert_eq!(latched(&mut rtc), [59, 59, 23, 0xFF, 0x01]);
+        // Day 511 rolls over to 0 and sets the carry, which stays set
+        rtc.do_cycle(TICKS_PER_SECOND);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0x00, 0x80]);
+        rtc.advance(86400);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0x01, 0x80]);
+    }
+
+    #[test]
+    fn halts_and_wraps_invalid_values() {
+        let mut rtc = emulated([10, 0, 0, 0, 0x40]);
+        rtc.do_cycle(10 * TICKS_PER_SECOND);
+        rtc.advance(100);
+        assert_eq!(latched(&mut rtc), [10, 0, 0, 0, 0x40]);
+
+        // Invalid seconds count up to 63 and wrap to 0 without carrying into the minutes
+        rtc.write(0, 62);
+        rtc.write(4, 0x00);
+        rtc.advance(2);
+        assert_eq!(latched(&mut rtc), [0, 0, 0, 0, 0x00]);
+        rtc.advance(61);
+        assert_eq!(latched(&mut rtc), [1, 1, 0, 0, 0x00]);
+
+        rtc.set(600 * 86400 + 5);
+        assert_eq!(latched(&mut rtc), [5, 0, 0, 88, 0x80]);
+    }
+}
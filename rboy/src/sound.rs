@@ -42,7 +42,7 @@ impl VolumeEnvelope {
                 if self.goes_up { 0x08 } else { 0 } |
                 (self.period & 0x7)
             },
-            _ => unimplemented!(),
+            _ => 0xFF,
         }
     }
 
@@ -206,7 +206,7 @@ impl SquareChannel {
                 if self.length.enabled { 0x40 } else { 0 } |
                 0x3F
             },
-            _ => unimplemented!(),
+            _ => 0xFF,
         }
     }
 
@@ -414,7 +414,7 @@ impl WaveChannel {
                     }
                 }
             },
-            _ => unimplemented!(),
+            _ => 0xFF,
         }
     }
 
@@ -596,7 +596,7 @@ impl NoiseChannel {
                 if self.length.enabled { 0x40 } else { 0 } |
                 0x3F
             },
-            _ => unimplemented!(),
+            _ => 0xFF,
         }
     }
 
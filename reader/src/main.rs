@@ -1,225 +1,215 @@
-extern crate rppal;
+extern crate clap;
+extern crate gbcart;
 
-use std::arch::asm;
-use std::clone::Clone;
-use rppal::gpio::{IoPin, Mode, OutputPin};
-use {
-    rppal::gpio::{Gpio, Level},
-    std::{
-        fs,
-        time::Duration,
-    },
-};
+use gbcart::{BusConfig, BusTiming, Cartridge, CartridgePins, Dat, DatMatch, FlashVariant, GpioPins};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
 
-const SLEEP: u64 = 2;
-
-const CS_PIN_ID: u8 = 18;
-const LATCH_PIN_ID: u8 = 24;
-const DATA_PIN_ID: u8 = 23;
-const CLOCK_PIN_ID: u8 = 25;
-const READ_PIN_ID: u8 = 15;
-const WRITE_PIN_ID: u8 = 14;
-
-const DATA_0_ID: u8 = 8;
-const DATA_1_ID: u8 = 7;
-const DATA_2_ID: u8 = 19;
-const DATA_3_ID: u8 = 12;
-const DATA_4_ID: u8 = 16;
-const DATA_5_ID: u8 = 20;
-const DATA_6_ID: u8 = 21;
-const DATA_7_ID: u8 = 26;
-
-const DATA_PINS: [u8; 8] = [
-    DATA_0_ID,
-    DATA_1_ID,
-    DATA_2_ID,
-    DATA_3_ID,
-    DATA_4_ID,
-    DATA_5_ID,
-    DATA_6_ID,
-    DATA_7_ID,
-];
-
-fn sleep(duration: Duration) {
-    for _ in 0..100 {
-        unsafe {
-            asm!(
-                "nop",
-                "nop",
-                "nop",
-                "nop",
-                "nop",
-                "nop",
-                "nop",
-                "nop",
-                "nop",
-                "nop",
-            );
-        }
+fn main() {
+    let matches = clap::Command::new("reader")
+        .about("Reads Game Boy cartridges through the Raspberry Pi GPIO adapter")
+        .arg(clap::Arg::new("output")
+            .help("File to write the ROM dump to")
+            .short('o')
+            .long("output")
+            .default_value("rom.dat"))
+        .arg(clap::Arg::new("retries")
+            .help("How often a bank is read again when two reads disagree")
+            .long("retries")
+            .value_parser(clap::value_parser!(u32))
+            .default_value("3"))
+        .arg(clap::Arg::new("dump-ram")
+            .help("Dumps the save RAM to FILE instead of reading the ROM")
+            .long("dump-ram")
+            .value_name("FILE"))
+        .arg(clap::Arg::new("restore-ram")
+            .help("Writes a .gbsave or .sav FILE to the save RAM of the cartridge")
+            .long("restore-ram")
+            .value_name("FILE")
+            .conflicts_with("dump-ram"))
+        .arg(clap::Arg::new("flash")
+            .help("Erases a flash cartridge and programs the .gb FILE onto it")
+            .long("flash")
+            .value_name("FILE")
+            .conflicts_with_all(["dump-ram", "restore-ram"]))
+        .arg(clap::Arg::new("flash-type")
+            .help("Command set of the flash chip")
+            .long("flash-type")
+            .value_parser(["29f016", "29lv", "29lv-swapped"])
+            .default_value("29f016"))
+        .arg(clap::Arg::new("info")
+            .help("Prints the cartridge header instead of reading the ROM")
+            .long("info")
+            .action(clap::ArgAction::SetTrue)
+            .conflicts_with_all(["dump-ram", "restore-ram", "flash"]))
+        .arg(clap::Arg::new("json")
+            .help("Prints the header as JSON, together with --info")
+            .long("json")
+            .requires("info")
+            .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("dat")
+            .help("Looks the dump up in a No-Intro style XML DAT FILE")
+            .long("dat")
+            .value_name("FILE")
+            .conflicts_with_all(["info", "dump-ram", "restore-ram", "flash"]))
+        .arg(clap::Arg::new("rename")
+            .help("Renames the dump to the name listed in the DAT file, cutting overdumps to the listed size")
+            .long("rename")
+            .requires("dat")
+            .action(clap::ArgAction::SetTrue))
+        .arg(clap::Arg::new("bus-config")
+            .help("Reads the pin assignments and bus delay of the adapter board from FILE")
+            .long("bus-config")
+            .value_name("FILE"))
+        .arg(clap::Arg::new("bus-delay")
+            .help("Sets the bus delay in nanoseconds, or auto to find the fastest reliable one")
+            .long("bus-delay")
+            .value_name("NS"))
+        .get_matches();
+
+    // Load the DAT first, so a typo does not cost a whole dump
+    let result = load_dat(&matches)
+        .and_then(|dat| bus_config(&matches).map(|config| (dat, config)))
+        .and_then(|(dat, config)| open_cartridge(&config).map(|cartridge| (dat, cartridge)))
+        .and_then(|(dat, mut cartridge)| run(&matches, &mut cartridge, dat.as_ref()));
+
+    match result {
+        // Keep the JSON output parseable
+        Ok(()) if matches.get_flag("json") => {},
+        Ok(()) => println!("Done"),
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        },
     }
 }
 
-fn shift_out(gpio: &mut GpioReader, value: u8) {
-    for number in (0..8).rev() {
-        gpio.clock_pin.set_low();
-        sleep(Duration::from_micros(SLEEP));
-        if (value >> number) & 1 == 1 {
-            gpio.data_pin.set_high();
-        } else {
-            gpio.data_pin.set_low();
+fn run(matches: &clap::ArgMatches, cartridge: &mut Cartridge<GpioPins>, dat: Option<&Dat>) -> Result<(), String> {
+    if matches.get_flag("info") {
+        print_info(cartridge, matches.get_flag("json"))
+    } else if let Some(path) = matches.get_one::<String>("dump-ram") {
+        dump_ram(cartridge, path)
+    } else if let Some(path) = matches.get_one::<String>("restore-ram") {
+        restore_ram(cartridge, path)
+    } else if let Some(path) = matches.get_one::<String>("flash") {
+        let variant = FlashVariant::from_name(matches.get_one::<String>("flash-type").unwrap()).unwrap();
+        flash_rom(cartridge, path, variant)
+    } else {
+        let retries = matches.get_one::<u32>("retries").copied().unwrap_or(gbcart::DEFAULT_RETRIES);
+        let path = matches.get_one::<String>("output").unwrap();
+        let data = dump_verified_rom(cartridge, path, retries)?;
+        match dat {
+            Some(dat) => identify_dump(dat, &data, path, matches.get_flag("rename")),
+            None => Ok(()),
         }
-        sleep(Duration::from_micros(SLEEP));
-        gpio.clock_pin.set_high();
-        sleep(Duration::from_micros(SLEEP));
     }
 }
 
-struct GpioReader {
-    pub gpio: Gpio,
-    pub cs_pin: OutputPin,
-    pub latch_pin: OutputPin,
-    pub clock_pin: OutputPin,
-    pub data_pin: OutputPin,
-    pub read_pin: OutputPin,
-    pub write_pin: OutputPin,
-    pub data_pins: Vec<IoPin>,
-    data_pins_mode: Mode,
+fn load_dat(matches: &clap::ArgMatches) -> Result<Option<Dat>, String> {
+    match matches.get_one::<String>("dat") {
+        Some(path) => Dat::from_file(path).map(Some).map_err(|e| e.to_string()),
+        None => Ok(None),
+    }
 }
 
-impl GpioReader {
-    fn new() -> Result<GpioReader, rppal::gpio::Error> {
-        let gpio = Gpio::new()?;
-        let cs_pin = gpio.get(CS_PIN_ID)?.into_output();
-        let latch_pin = gpio.get(LATCH_PIN_ID)?.into_output();
-        let clock_pin = gpio.get(CLOCK_PIN_ID)?.into_output();
-        let data_pin = gpio.get(DATA_PIN_ID)?.into_output();
-        let read_pin = gpio.get(READ_PIN_ID)?.into_output();
-        let write_pin = gpio.get(WRITE_PIN_ID)?.into_output();
-        let data_pins_mode = Mode::Input;
-        let data_pins = DATA_PINS.iter().map(|pin| gpio.get(*pin).unwrap().into_io(data_pins_mode)).collect();
-        Ok(GpioReader {
-            gpio,
-            cs_pin,
-            latch_pin,
-            clock_pin,
-            data_pin,
-            read_pin,
-            write_pin,
-            data_pins,
-            data_pins_mode,
-        })
-    }
-
-    fn change_data_pins_mode(&mut self, mode: Mode) {
-        if self.data_pins_mode == mode {
-            return;
-        }
-        if mode == Mode::Input {
-            for pin in self.data_pins.iter_mut() {
-                pin.set_low();
-            }
-        }
-        for pin in self.data_pins.iter_mut() {
-            pin.set_mode(mode);
-        }
-        self.data_pins_mode = mode;
+fn open_cartridge(config: &BusConfig) -> Result<Cartridge<GpioPins>, String> {
+    let cartridge = Cartridge::open_with(config).map_err(|e| e.to_string())?;
+    if config.timing == BusTiming::Calibrate {
+        // On stderr, as stdout may carry JSON
+        eprintln!("Calibrated bus delay: {} ns", cartridge.delay().as_nanos());
     }
+    Ok(cartridge)
 }
 
-fn main() {
-    println!("Start reading");
-    let mut gpio = GpioReader::new().unwrap();
-    gpio.cs_pin.set_high();
-    gpio.read_pin.set_high();
-    gpio.write_pin.set_high();
-    sleep(Duration::from_micros(SLEEP));
-    let mut data: Vec<u8> = Vec::new();
-    // Read the data from bank 0
-    read_next_rom_bank(&mut gpio, &mut data, 0);
-    let mut banks_count = get_banks_per_rom(&data);
-    disable_ram(&mut gpio);
-    println!("Banks count: {}", banks_count);
-    for bank in 1..banks_count {
-        println!("Reading bank {}..", bank);
-        select_rom_bank(&mut gpio, bank);
-        read_next_rom_bank(&mut gpio, &mut data, 0x4000);
+fn bus_config(matches: &clap::ArgMatches) -> Result<BusConfig, String> {
+    let mut config = match matches.get_one::<String>("bus-config") {
+        Some(path) => BusConfig::from_file(path).map_err(|e| e.to_string())?,
+        None => BusConfig::default(),
+    };
+    if let Some(delay) = matches.get_one::<String>("bus-delay") {
+        config.timing = BusTiming::parse(delay).map_err(|e| e.to_string())?;
     }
-    fs::write("rom.dat", data).unwrap();
-    println!("Done");
+    Ok(config)
 }
 
-fn disable_ram(gpio: &mut GpioReader) {
-    write_value_to_address(gpio, 0x0000, 0);
+fn print_info<P: CartridgePins>(cartridge: &mut Cartridge<P>, json: bool) -> Result<(), String> {
+    let header = cartridge.read_header();
+    if json {
+        println!("{}", header.to_json());
+    } else {
+        println!("{}", header);
+    }
+    if header.checksum_ok() {
+        Ok(())
+    } else {
+        Err("The header checksum does not match, check the cartridge contacts".to_owned())
+    }
 }
 
-fn get_banks_per_rom(data: &[u8]) -> u16 {
-    println!("Cartridge type: {:#04x}", data[0x0147]);
-    println!("Rom type: {:#04x}", data[0x0148]);
-    match data[0x0148] {
-        0x00 => 2,
-        0x01 => 4,
-        0x02 => 8,
-        0x03 => 16,
-        0x04 => 32,
-        0x05 => 64,
-        0x06 => 128,
-        0x07 => 256,
-        0x52 => 72,
-        0x53 => 80,
-        0x54 => 96,
-        _ => 0,
+fn dump_verified_rom<P: CartridgePins>(cartridge: &mut Cartridge<P>, path: &str, retries: u32) -> Result<Vec<u8>, String> {
+    println!("Start reading");
+    let (data, report) = cartridge.read_rom(retries);
+    fs::write(path, &data).map_err(|e| format!("Could not write {}: {}", path, e))?;
+    report.print();
+    if report.passed() {
+        Ok(data)
+    } else {
+        Err(format!("The dump in {} failed verification, check the cartridge contacts", path))
     }
 }
 
-fn read_next_rom_bank(gpio: &mut GpioReader, data: &mut Vec<u8>, start_address: u16) {
-    for address in start_address..=(start_address + 0x3FFF) {
-        data.push(read_byte(gpio, address));
+fn identify_dump(dat: &Dat, data: &[u8], path: &str, rename: bool) -> Result<(), String> {
+    let found = dat.lookup(data);
+    println!("{}", found);
+    match found {
+        DatMatch::Verified(entry) | DatMatch::BadDump(entry) if rename => {
+            let target = renamed_path(path, &entry.rom_name)?;
+            fs::rename(path, &target).map_err(|e| format!("Could not rename {} to {}: {}", path, target.display(), e))?;
+            println!("Renamed {} to {}", path, target.display());
+            Ok(())
+        },
+        DatMatch::Overdump(entry) if rename => {
+            // Only the start of the dump is the game, so the rest is cut off
+            let target = renamed_path(path, &entry.rom_name)?;
+            fs::write(&target, &data[..entry.size]).map_err(|e| format!("Could not write {}: {}", target.display(), e))?;
+            if target != Path::new(path) {
+                fs::remove_file(path).map_err(|e| format!("Could not remove {}: {}", path, e))?;
+            }
+            println!("Truncated {} to {} bytes as {}", path, entry.size, target.display());
+            Ok(())
+        },
+        _ => Ok(()),
     }
 }
 
-fn read_byte(gpio: &mut GpioReader, address: u16) -> u8 {
-    write_address(gpio, address);
-    gpio.cs_pin.set_low();
-    gpio.read_pin.set_low();
-    sleep(Duration::from_micros(SLEEP));
-    let mut value = 0u8;
-    for (bit, pin) in gpio.data_pins.iter().enumerate() {
-        if pin.read() == Level::High {
-            value |= (1 << bit) as u8;
-        }
+/// The dump at `path` renamed to `rom_name` from the DAT, which must not lead out of its directory
+fn renamed_path(path: &str, rom_name: &str) -> Result<PathBuf, String> {
+    let mut components = Path::new(rom_name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if !rom_name.contains(['/', '\\']) => Ok(Path::new(path).with_file_name(rom_name)),
+        _ => Err(format!("Not renaming to {:?} from the DAT, as it is not a plain file name", rom_name)),
     }
-    gpio.read_pin.set_high();
-    gpio.cs_pin.set_high();
-    value
 }
 
-fn select_rom_bank(gpio: &mut GpioReader, bank: u16) {
-    write_value_to_address(gpio, 0x2000, bank &0xff);
-    write_value_to_address(gpio, 0x3000, (bank >> 8) & 1);
+fn flash_rom<P: CartridgePins>(cartridge: &mut Cartridge<P>, path: &str, variant: FlashVariant) -> Result<(), String> {
+    let rom = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    let bad_banks = cartridge.flash(variant, &rom).map_err(|e| e.to_string())?;
+    if bad_banks.is_empty() {
+        println!("Verification PASSED");
+        Ok(())
+    } else {
+        Err(format!("Verification FAILED for banks {:?}", bad_banks))
+    }
 }
 
-fn write_address(gpio: &mut GpioReader, address: u16) {
-    gpio.latch_pin.set_low();
-    shift_out(gpio, ((address >> 8) as u8));
-    shift_out(gpio, ((address & 0xFF) as u8));
-    gpio.latch_pin.set_high();
+fn dump_ram<P: CartridgePins>(cartridge: &mut Cartridge<P>, path: &str) -> Result<(), String> {
+    let data = cartridge.read_ram().map_err(|e| e.to_string())?;
+    println!("Read {} bytes of save RAM", data.len());
+    fs::write(path, data).map_err(|e| format!("Could not write {}: {}", path, e))
 }
 
-fn write_value_to_address(gpio: &mut GpioReader, address: u16, value: u16) {
-    write_address(gpio, address);
-    gpio.change_data_pins_mode(Mode::Output);
-    for (index, mut pin) in gpio.data_pins.iter_mut().enumerate() {
-        if value & (1 << index) != 0 {
-            pin.set_high()
-        } else {
-            pin.set_low();
-        }
-    }
-    gpio.cs_pin.set_low();
-    gpio.write_pin.set_low();
-    sleep(Duration::from_micros(SLEEP));
-    gpio.write_pin.set_high();
-    gpio.cs_pin.set_high();
-    gpio.change_data_pins_mode(Mode::Input);
-    sleep(Duration::from_micros(SLEEP));
+fn restore_ram<P: CartridgePins>(cartridge: &mut Cartridge<P>, path: &str) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+    println!("Writing {} bytes of save RAM", data.len());
+    cartridge.write_ram(&data).map_err(|e| e.to_string())
 }